@@ -0,0 +1,272 @@
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::error::KrEvalError;
+use crate::kr::Kr;
+use crate::operator::atom_cmp;
+use crate::sym::Sym;
+
+/*
+Attributes record a property of a list so that searching it can skip the linear scan
+    `s#x    sorted ascending, searched by binary search
+    `u#x    unique items, searched through a hash index
+    `p#x    parted, equal items are next to each other
+    `g#x    grouped, any list, a hash index of where each item is
+    `#x     removes the attribute
+The property is checked when the attribute is applied
+Take and drop of a range keep the attribute, every other verb sees the plain list
+The hash index of a range is only built when the range is first searched
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Attr {
+    Sorted,
+    Unique,
+    Parted,
+    Grouped,
+}
+
+impl Attr {
+    pub fn from_name(name: &[u8]) -> Option<Attr> {
+        match name {
+            b"s" => Some(Attr::Sorted),
+            b"u" => Some(Attr::Unique),
+            b"p" => Some(Attr::Parted),
+            b"g" => Some(Attr::Grouped),
+            _ => None,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Attr::Sorted => "s",
+            Attr::Unique => "u",
+            Attr::Parted => "p",
+            Attr::Grouped => "g",
+        }
+    }
+}
+
+// Hashable form of an atom, two keys are equal exactly when the atoms are
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Key {
+    Int(i16, i64),
+    Float(i16, u64),
+    Sym(Sym),
+    Text(Vec<u8>),
+}
+
+fn key(x: &Kr) -> Option<Key> {
+    // Nulls are equal to each other and 0.0 to -0.0, so they share their bits
+    fn bits(n: f64) -> u64 {
+        if n.is_nan() { f64::NAN.to_bits() } else if n == 0.0 { 0 } else { n.to_bits() }
+    }
+    if let Some(s) = x.symbols() { return key(&s) };
+    match x {
+        Kr::B(n) => Some(Key::Int(-1, *n as i64)),
+        Kr::I(n) => Some(Key::Int(-6, *n as i64)),
+        Kr::J(n) => Some(Key::Int(-7, *n)),
+        Kr::E(n) => Some(Key::Float(-8, bits(*n as f64))),
+        Kr::F(n) => Some(Key::Float(-9, bits(*n))),
+        Kr::C(c) => Some(Key::Int(-10, *c as i64)),
+        Kr::S(s) => Some(Key::Sym(*s)),
+        Kr::Cv(cv) => Some(Key::Text(cv.clone())),
+        _ => None,
+    }
+}
+
+// Positions of each item of a list, None if some item cannot be hashed
+fn positions(x: &Kr) -> Option<HashMap<Key, Vec<usize>>> {
+    let mut index: HashMap<Key, Vec<usize>> = HashMap::new();
+    for (i, item) in x.items().iter().enumerate() {
+        index.entry(key(item)?).or_default().push(i);
+    }
+    Some(index)
+}
+
+type Index = HashMap<Key, Vec<usize>>;
+
+#[derive(Clone, Debug)]
+pub struct Attribute {
+    attr: Attr,
+    // Positions of each item, filled on first use, sorted lists need none
+    index: Rc<OnceCell<Option<Index>>>,
+}
+
+impl Attribute {
+    fn new(attr: Attr, index: Option<Index>) -> Self {
+        let cell = OnceCell::new();
+        if let Some(index) = index { let _ = cell.set(Some(index)); };
+        Attribute { attr, index: Rc::new(cell) }
+    }
+
+    pub fn attr(&self) -> Attr {
+        self.attr
+    }
+
+    // Hash index of the list x the attribute is on, None for a sorted list
+    fn index(&self, x: &Kr) -> Option<&Index> {
+        if self.attr == Attr::Sorted { return None };
+        self.index.get_or_init(|| positions(x)).as_ref()
+    }
+
+    // Index of the first item of x equal to y
+    pub fn find(&self, x: &Kr, y: &Kr) -> Option<usize> {
+        match self.index(x) {
+            Some(index) => index.get(&key(y)?).and_then(|p| p.first().copied()),
+            None if self.attr == Attr::Sorted => {
+                let (lo, hi) = equal_range(x, y)?;
+                (lo..hi).find(|i| x.item(*i).as_ref() == Some(y))
+            },
+            None => x.items().iter().position(|k| k == y),
+        }
+    }
+
+    // Where x=y is true, for an atom y, when the attribute knows without comparing every item
+    fn equal(&self, x: &Kr, y: &Kr) -> Option<Vec<usize>> {
+        match self.index(x) {
+            // Hash keys only match = when y has the type of the items
+            Some(index) if symbolic_code(y) == -symbolic_code(x) => {
+                Some(index.get(&key(y)?).cloned().unwrap_or_default())
+            },
+            Some(_) => None,
+            None if self.attr == Attr::Sorted => equal_range(x, y).map(|(lo, hi)| (lo..hi).collect()),
+            None => None,
+        }
+    }
+}
+
+// Type code with enumerations counted as symbols
+fn symbolic_code(x: &Kr) -> i16 {
+    match x.type_code() {
+        20 => 11,
+        -20 => -11,
+        code => code,
+    }
+}
+
+// First index where f turns false, f must be true then false across 0..n
+fn bisect(n: usize, f: impl Fn(usize) -> bool) -> usize {
+    let (mut lo, mut hi) = (0, n);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if f(mid) { lo = mid + 1 } else { hi = mid };
+    }
+    lo
+}
+
+// The run of items of a sorted list that compare equal to y
+fn equal_range(x: &Kr, y: &Kr) -> Option<(usize, usize)> {
+    let n = x.count()?;
+    let cmp = |i: usize| x.item(i).and_then(|k| atom_cmp(&k, y));
+    if n > 0 && cmp(0).is_none() { return None };
+    let lo = bisect(n, |i| cmp(i) == Some(Ordering::Less));
+    let hi = bisect(n, |i| cmp(i) != Some(Ordering::Greater));
+    Some((lo, hi))
+}
+
+// x with an attribute, after checking that x has the property
+pub fn apply(attr: Attr, x: &Kr) -> Result<Kr, KrEvalError> {
+    let x = x.plain();
+    let n = x.count().ok_or(KrEvalError::type_of(&[x]))?;
+    let attribute = match attr {
+        Attr::Sorted => {
+            let sorted = (1..n).all(|i| {
+                let (a, b) = (x.item(i - 1), x.item(i));
+                matches!(a.zip(b).and_then(|(a, b)| atom_cmp(&a, &b)), Some(Ordering::Less | Ordering::Equal))
+            });
+            if !sorted { return Err(KrEvalError::AttrFail(attr.name())) };
+            Attribute::new(attr, None)
+        },
+        _ => {
+            let index = positions(x).ok_or(KrEvalError::type_of(&[x]))?;
+            let ok = match attr {
+                Attr::Unique => index.values().all(|p| p.len() == 1),
+                Attr::Parted => index.values().all(|p| p[p.len() - 1] - p[0] + 1 == p.len()),
+                _ => true,
+            };
+            if !ok { return Err(KrEvalError::AttrFail(attr.name())) };
+            Attribute::new(attr, Some(index))
+        },
+    };
+    Ok(Kr::Attr(attribute, Box::new(x.clone())))
+}
+
+// A range of the items of y keeps the attribute y had, its index is built when it is searched
+pub fn keep_range(y: &Kr, res: Kr) -> Kr {
+    let Kr::Attr(a, _) = y else { return res };
+    Kr::Attr(Attribute::new(a.attr, None), Box::new(res))
+}
+
+// Arguments without their attributes, for verbs that do not use them
+pub fn strip(args: &[Kr]) -> Cow<'_, [Kr]> {
+    if !args.iter().any(|k| matches!(k, Kr::Attr(_, _))) { return Cow::Borrowed(args) };
+    Cow::Owned(args.iter().map(|k| k.plain().clone()).collect())
+}
+
+// x=y searched through the attribute of a list x or y, compared with an atom
+pub fn equal(x: &Kr, y: &Kr) -> Option<Kr> {
+    let (list, atom) = match (x, y) {
+        (Kr::Attr(_, _), _) if y.count().is_none() => (x, y),
+        (_, Kr::Attr(_, _)) if x.count().is_none() => (y, x),
+        _ => return None,
+    };
+    let Kr::Attr(a, list) = list else { unreachable!() };
+    let found = a.equal(list, atom)?;
+    let mut bv = vec![false; list.count()?];
+    for i in found { bv[i] = true };
+    Some(Kr::Bv(bv))
+}
+
+// Index of the last item of a sorted list x that is not greater than y, or -1
+pub fn bin(x: &Kr, y: &Kr) -> Result<Kr, KrEvalError> {
+    let x = x.plain();
+    let n = x.count().ok_or(KrEvalError::type_of(&[x, y]))?;
+    let one = |y: &Kr| -> Result<i64, KrEvalError> {
+        if n > 0 && x.item(0).and_then(|k| atom_cmp(&k, y)).is_none() { return Err(KrEvalError::type_of(&[x, y])) };
+        let i = bisect(n, |i| x.item(i).and_then(|k| atom_cmp(&k, y)) != Some(Ordering::Greater));
+        Ok(i as i64 - 1)
+    };
+    match y.count() {
+        None => Ok(Kr::J(one(y)?)),
+        Some(_) => Ok(Kr::Jv(y.items().iter().map(one).collect::<Result<Vec<i64>, KrEvalError>>()?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+
+    fn shown(lines: &[&str]) -> Option<String> {
+        run(lines).map(|x| x.print())
+    }
+
+    // The property is checked when the attribute is applied
+    #[test]
+    fn attribute_checks() {
+        assert_eq!(shown(&["`u#1,2,1"]), None);
+        assert_eq!(shown(&["`u#1,2,3"]), Some("`u#1 2 3".to_string()));
+        assert_eq!(shown(&["`s#3,1"]), None);
+        assert_eq!(shown(&["`p#1,1,2,1"]), None);
+        assert_eq!(shown(&["`p#1,1,2"]), Some("`p#1 1 2".to_string()));
+        assert_eq!(shown(&["`g#1,1"]), Some("`g#1 1".to_string()));
+    }
+
+    #[test]
+    fn search_through_attributes() {
+        assert_eq!(shown(&["x:`u#10,20,30", "x?20"]), Some("1".to_string()));
+        assert_eq!(shown(&["x:`u#10,20,30", "x?25"]), Some("3".to_string()));
+        assert_eq!(shown(&["x:`s#10,20,30", "x?30"]), Some("2".to_string()));
+        assert_eq!(shown(&["x:`g#3,1,3", "x=3"]), Some("101b".to_string()));
+    }
+
+    // Ranges keep the attribute, appending drops it
+    #[test]
+    fn attributes_kept_by_ranges() {
+        assert_eq!(shown(&["2_`s#1,2,3,4"]), Some("`s#3 4".to_string()));
+        assert_eq!(shown(&["-1_`u#1,2,3"]), Some("`u#1 2".to_string()));
+        assert_eq!(shown(&["x:`u#10,20", "x,:10", "x"]), Some("10 20 10".to_string()));
+    }
+}
@@ -0,0 +1,202 @@
+use crate::error::KrEvalError;
+use crate::kr::{Kr, NULL_I, NULL_J};
+use crate::sym::Sym;
+
+/*
+Conversion between Kr types
+x$y casts y to the type named by x, either a name or a single character code:
+    `boolean "b"    `int "i"    `long "j"    `real "e"    `float "f"    `char "c"    `symbol "s" `
+An upper case character code parses strings instead: "J"$"42"
+Anything that cannot be represented in the target type becomes its null
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KrType {
+    Boolean,
+    Int,
+    Long,
+    Real,
+    Float,
+    Char,
+    Symbol,
+}
+
+impl KrType {
+    // Type from its name or single character code
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"boolean" | b"b" => Some(KrType::Boolean),
+            b"int" | b"i" => Some(KrType::Int),
+            b"long" | b"j" => Some(KrType::Long),
+            b"real" | b"e" => Some(KrType::Real),
+            b"float" | b"f" => Some(KrType::Float),
+            b"char" | b"c" => Some(KrType::Char),
+            b"symbol" | b"s" | b"" => Some(KrType::Symbol),
+            _ => None,
+        }
+    }
+
+    // An empty vector of this type
+    fn empty(&self) -> Kr {
+        match self {
+            KrType::Boolean => Kr::Bv(Vec::new()),
+            KrType::Int => Kr::Iv(Vec::new()),
+            KrType::Long => Kr::Jv(Vec::new()),
+            KrType::Real => Kr::Ev(Vec::new()),
+            KrType::Float => Kr::Fv(Vec::new()),
+            KrType::Char => Kr::Cv(Vec::new()),
+            KrType::Symbol => Kr::Sv(Vec::new()),
+        }
+    }
+}
+
+// Whether a name or character code names a type, rather than an enumeration domain
+pub fn is_type_name(name: &[u8]) -> bool {
+    match name {
+        [c] if c.is_ascii_uppercase() => KrType::from_name(&[c.to_ascii_lowercase()]).is_some(),
+        _ => KrType::from_name(name).is_some(),
+    }
+}
+
+pub fn cast(x: &Kr, y: &Kr) -> Result<Kr, KrEvalError> {
+    let name: Vec<u8> = match x {
+        Kr::S(s) => s.text().0,
+        Kr::C(c) => vec![*c],
+        Kr::Cv(cv) if cv.len() == 1 => cv.clone(),
+        _ => return Err(KrEvalError::type_of(&[x, y])),
+    };
+    match name[..] {
+        [c] if c.is_ascii_uppercase() => {
+            let t = KrType::from_name(&[c.to_ascii_lowercase()]).ok_or(KrEvalError::Domain)?;
+            parse(t, y)
+        },
+        _ => {
+            let t = KrType::from_name(&name).ok_or(KrEvalError::Domain)?;
+            convert(t, y)
+        },
+    }
+}
+
+// Rebuild a list from converted items, keeping the target type when it is empty
+fn collect(t: KrType, items: Vec<Kr>) -> Kr {
+    if items.is_empty() { t.empty() } else { Kr::from_items(items) }
+}
+
+fn convert(t: KrType, y: &Kr) -> Result<Kr, KrEvalError> {
+    if let Some(s) = y.symbols() { return convert(t, &s) };
+    match y {
+        // A whole string becomes a single symbol
+        Kr::Cv(cv) if t == KrType::Symbol => Ok(Kr::S(Sym::new(cv))),
+        Kr::NN(list) => Ok(Kr::from_items(list.iter().map(|k| convert(t, k)).collect::<Result<Vec<Kr>, KrEvalError>>()?)),
+        _ => match y.count() {
+            None => convert_atom(t, y),
+            Some(_) => Ok(collect(t, y.items().iter().map(|k| convert_atom(t, k)).collect::<Result<Vec<Kr>, KrEvalError>>()?)),
+        },
+    }
+}
+
+// Numeric value of an atom
+pub enum Num {
+    Int(i64),
+    Float(f64),
+    Null,
+}
+
+pub fn num(y: &Kr) -> Option<Num> {
+    match y {
+        Kr::B(b) => Some(Num::Int(*b as i64)),
+        Kr::I(n) => Some(if *n == NULL_I { Num::Null } else { Num::Int(*n as i64) }),
+        Kr::J(n) => Some(if *n == NULL_J { Num::Null } else { Num::Int(*n) }),
+        Kr::E(n) => Some(if n.is_nan() { Num::Null } else { Num::Float(*n as f64) }),
+        Kr::F(n) => Some(if n.is_nan() { Num::Null } else { Num::Float(*n) }),
+        Kr::C(c) => Some(Num::Int(*c as i64)),
+        _ => None,
+    }
+}
+
+// Integer part of a number, None if it has no integer representation
+fn whole(n: &Num) -> Option<i64> {
+    match n {
+        Num::Int(i) => Some(*i),
+        Num::Float(f) if f.is_finite() && f.abs() < i64::MAX as f64 => Some(f.round() as i64),
+        _ => None,
+    }
+}
+
+pub fn real(n: &Num) -> f64 {
+    match n {
+        Num::Int(i) => *i as f64,
+        Num::Float(f) => *f,
+        Num::Null => f64::NAN,
+    }
+}
+
+fn convert_atom(t: KrType, y: &Kr) -> Result<Kr, KrEvalError> {
+    match (t, y) {
+        (KrType::Symbol, Kr::S(_)) => return Ok(y.clone()),
+        (KrType::Symbol, Kr::C(c)) => return Ok(Kr::S(Sym::new(&[*c]))),
+        (_, Kr::S(_)) | (KrType::Symbol, _) => return Err(KrEvalError::type_of(&[y])),
+        _ => {},
+    }
+    let n = num(y).ok_or(KrEvalError::type_of(&[y]))?;
+    let res = match t {
+        KrType::Boolean => Kr::B(matches!(n, Num::Int(i) if i != 0) || matches!(n, Num::Float(f) if f != 0.0)),
+        KrType::Int => Kr::I(whole(&n).and_then(|i| i32::try_from(i).ok()).unwrap_or(NULL_I)),
+        KrType::Long => Kr::J(whole(&n).unwrap_or(NULL_J)),
+        KrType::Real => Kr::E(real(&n) as f32),
+        KrType::Float => Kr::F(real(&n)),
+        KrType::Char => Kr::C(whole(&n).map(|i| i as u8).unwrap_or(b' ')),
+        KrType::Symbol => unreachable!(),
+    };
+    Ok(res)
+}
+
+fn parse(t: KrType, y: &Kr) -> Result<Kr, KrEvalError> {
+    match y {
+        Kr::Cv(cv) => Ok(parse_text(t, cv)),
+        Kr::C(c) => Ok(parse_text(t, &[*c])),
+        Kr::NN(list) => Ok(Kr::from_items(list.iter().map(|k| parse(t, k)).collect::<Result<Vec<Kr>, KrEvalError>>()?)),
+        _ => Err(KrEvalError::type_of(&[y])),
+    }
+}
+
+// Parse text as an atom of the given type, null if it cannot be parsed
+pub fn parse_text(t: KrType, text: &[u8]) -> Kr {
+    let s = String::from_utf8_lossy(text);
+    let s = s.trim();
+    match t {
+        KrType::Boolean => Kr::B(matches!(s, "1" | "1b" | "t" | "T" | "true" | "y" | "Y")),
+        KrType::Int => Kr::I(parse_int(s).and_then(|n| i32::try_from(n).ok()).unwrap_or(NULL_I)),
+        KrType::Long => Kr::J(parse_int(s).unwrap_or(NULL_J)),
+        KrType::Real => Kr::E(parse_float(s) as f32),
+        KrType::Float => Kr::F(parse_float(s)),
+        KrType::Char => Kr::Cv(text.to_vec()),
+        KrType::Symbol => Kr::S(Sym::from_str(s)),
+    }
+}
+
+fn parse_int(s: &str) -> Option<i64> {
+    s.parse::<i64>().ok().filter(|n| *n != NULL_J)
+}
+
+fn parse_float(s: &str) -> f64 {
+    match s {
+        "0w" => f64::INFINITY,
+        "-0w" => f64::NEG_INFINITY,
+        _ => s.parse().unwrap_or(f64::NAN),
+    }
+}
+
+// Text representation of x, lists give a list of strings
+pub fn string(x: &Kr) -> Kr {
+    if let Some(s) = x.symbols() { return string(&s) };
+    match x {
+        Kr::C(c) => Kr::Cv(vec![*c]),
+        Kr::S(s) => Kr::Cv(s.text().0),
+        Kr::Null => Kr::Cv(b"::".to_vec()),
+        _ => match x.count() {
+            None => Kr::Cv(x.print().into_bytes()),
+            Some(_) => Kr::NN(x.items().iter().map(string).collect()),
+        },
+    }
+}
@@ -0,0 +1,260 @@
+use std::fs::OpenOptions;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/*
+Line editor for the prompt, used when stdin is a terminal
+    Left Right Home End, Ctrl-B Ctrl-F Ctrl-A Ctrl-E   move the cursor
+    Backspace Delete, Ctrl-K Ctrl-U Ctrl-W              delete a character, to the end, to the start, a word
+    Up Down, Ctrl-P Ctrl-N                              step through history
+    Ctrl-R                                              search history backwards, again for an older match
+    Ctrl-C                                              abandon the line, Ctrl-D on an empty line ends input
+The terminal is put in raw mode with stty only while a line is being read
+History is kept in ~/.kr_history, one entry per line
+*/
+
+const HISTORY_FILE: &str = ".kr_history";
+const HISTORY_SIZE: usize = 1000;
+
+pub struct Editor {
+    history: Vec<String>,
+    path: Option<PathBuf>,
+}
+
+// Raw mode for as long as it lives, the saved settings are restored when dropped
+struct Raw(String);
+
+impl Raw {
+    fn enter() -> Option<Raw> {
+        let saved = stty(&["-g"])?;
+        stty(&["-icanon", "-echo", "-isig", "-ixon", "min", "1"])?;
+        Some(Raw(saved.trim().to_string()))
+    }
+}
+
+impl Drop for Raw {
+    fn drop(&mut self) {
+        stty(&[&self.0]);
+    }
+}
+
+fn stty(args: &[&str]) -> Option<String> {
+    let out = Command::new("stty").args(args).stdin(Stdio::inherit()).stderr(Stdio::null()).output().ok()?;
+    if !out.status.success() { return None };
+    String::from_utf8(out.stdout).ok()
+}
+
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    Up,
+    Down,
+    Ctrl(u8),
+    Escape,
+    Eof,
+}
+
+// Next key pressed, decoding escape sequences and UTF-8
+fn key(input: &mut impl Read) -> Key {
+    let byte = |input: &mut dyn Read| -> Option<u8> {
+        let mut b = [0u8];
+        match input.read(&mut b) {
+            Ok(1) => Some(b[0]),
+            _ => None,
+        }
+    };
+    let Some(b) = byte(input) else { return Key::Eof };
+    match b {
+        b'\r' | b'\n' => Key::Enter,
+        127 | 8 => Key::Backspace,
+        0x1b => match byte(input) {
+            Some(b'[') | Some(b'O') => match byte(input) {
+                Some(b'A') => Key::Up,
+                Some(b'B') => Key::Down,
+                Some(b'C') => Key::Right,
+                Some(b'D') => Key::Left,
+                Some(b'H') => Key::Home,
+                Some(b'F') => Key::End,
+                // Longer sequences end in ~: Home 1~ Delete 3~ End 4~
+                Some(d @ b'0'..=b'9') => {
+                    let mut last = d;
+                    while last != b'~' {
+                        match byte(input) {
+                            Some(c) => last = c,
+                            None => return Key::Eof,
+                        }
+                    }
+                    match d {
+                        b'1' | b'7' => Key::Home,
+                        b'3' => Key::Delete,
+                        b'4' | b'8' => Key::End,
+                        _ => Key::Escape,
+                    }
+                },
+                _ => Key::Escape,
+            },
+            _ => Key::Escape,
+        },
+        0..=31 => Key::Ctrl(b + b'@'),
+        _ => {
+            // A multi-byte character continues for as many bytes as its first byte says
+            let n = b.leading_ones() as usize;
+            let mut bytes = vec![b];
+            for _ in 1..n.clamp(1, 4) {
+                match byte(input) {
+                    Some(c) => bytes.push(c),
+                    None => return Key::Eof,
+                }
+            }
+            String::from_utf8(bytes).ok().and_then(|s| s.chars().next()).map_or(Key::Escape, Key::Char)
+        },
+    }
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        let path = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+        let mut history: Vec<String> = path.as_ref()
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .map(|text| text.lines().filter(|l| !l.trim().is_empty()).map(String::from).collect())
+            .unwrap_or_default();
+        // The file is only appended to while running, so it is cut back to the newest entries here
+        if history.len() > HISTORY_SIZE {
+            history.drain(..history.len() - HISTORY_SIZE);
+            if let Some(p) = &path {
+                let _ = std::fs::write(p, history.iter().map(|h| format!("{}\n", h)).collect::<String>());
+            }
+        }
+        Editor { history, path }
+    }
+
+    // Remember an entry, in memory and in the history file
+    // Newlines are whitespace to kr, so an entry typed across lines is kept on one
+    pub fn add(&mut self, entry: &str) {
+        let entry = entry.trim().replace(['\n', '\r'], " ");
+        if entry.is_empty() || self.history.last() == Some(&entry) { return };
+        self.history.push(entry.clone());
+        if self.history.len() > HISTORY_SIZE { self.history.remove(0); };
+        if let Some(path) = &self.path {
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(file, "{}", entry);
+            }
+        }
+    }
+
+    // A line typed at the prompt, None at the end of input
+    // Falls back to reading a plain line if the terminal cannot be put in raw mode
+    pub fn read_line(&mut self, prompt: &str) -> Option<String> {
+        print!("{}", prompt);
+        io::stdout().flush().ok()?;
+        let Some(_raw) = Raw::enter() else {
+            let mut input = String::new();
+            return match io::stdin().read_line(&mut input) {
+                Ok(0) | Err(_) => None,
+                Ok(_) => Some(input),
+            };
+        };
+        let mut line: Vec<char> = Vec::new();
+        let mut cursor = 0;
+        // Position in history, history.len() is the line being typed
+        let mut place = self.history.len();
+        let mut typed: Vec<char> = Vec::new();
+        let mut stdin = io::stdin().lock();
+        loop {
+            match key(&mut stdin) {
+                Key::Enter => break,
+                Key::Eof => return None,
+                Key::Ctrl(b'D') if line.is_empty() => { println!(); return None },
+                Key::Char(c) => { line.insert(cursor, c); cursor += 1 },
+                Key::Backspace | Key::Ctrl(b'H') if cursor > 0 => { cursor -= 1; line.remove(cursor); },
+                Key::Delete | Key::Ctrl(b'D') if cursor < line.len() => { line.remove(cursor); },
+                Key::Left | Key::Ctrl(b'B') => cursor = cursor.saturating_sub(1),
+                Key::Right | Key::Ctrl(b'F') => cursor = (cursor + 1).min(line.len()),
+                Key::Home | Key::Ctrl(b'A') => cursor = 0,
+                Key::End | Key::Ctrl(b'E') => cursor = line.len(),
+                Key::Ctrl(b'K') => line.truncate(cursor),
+                Key::Ctrl(b'U') => { line.drain(..cursor); cursor = 0 },
+                Key::Ctrl(b'W') => {
+                    let mut start = cursor;
+                    while start > 0 && line[start - 1].is_whitespace() { start -= 1 };
+                    while start > 0 && !line[start - 1].is_whitespace() { start -= 1 };
+                    line.drain(start..cursor);
+                    cursor = start;
+                },
+                Key::Ctrl(b'C') => {
+                    println!("^C");
+                    line.clear();
+                    cursor = 0;
+                    place = self.history.len();
+                    print!("{}", prompt);
+                },
+                Key::Up | Key::Ctrl(b'P') if place > 0 => {
+                    if place == self.history.len() { typed = line.clone() };
+                    place -= 1;
+                    line = self.history[place].chars().collect();
+                    cursor = line.len();
+                },
+                Key::Down | Key::Ctrl(b'N') if place < self.history.len() => {
+                    place += 1;
+                    line = if place == self.history.len() { typed.clone() } else { self.history[place].chars().collect() };
+                    cursor = line.len();
+                },
+                Key::Ctrl(b'R') => {
+                    let (found, run) = self.search(&mut stdin, prompt);
+                    if let Some(found) = found {
+                        line = found.chars().collect();
+                        cursor = line.len();
+                    }
+                    if run { redraw(prompt, &line, cursor); break };
+                },
+                _ => {},
+            }
+            redraw(prompt, &line, cursor);
+        }
+        println!();
+        Some(line.into_iter().collect::<String>() + "\n")
+    }
+
+    // Reverse incremental search, Enter runs the match, a cursor key takes it to edit
+    // and Escape or Ctrl-G gives up, returns the match and whether to run it
+    fn search(&self, input: &mut impl Read, prompt: &str) -> (Option<String>, bool) {
+        let mut pattern = String::new();
+        // Only entries before this one are searched, Ctrl-R moves it back past the current match
+        let mut before = self.history.len();
+        loop {
+            let found = self.history[..before].iter().rposition(|h| h.contains(&pattern));
+            let shown = found.map_or("", |i| &self.history[i]);
+            print!("\r\x1b[K(search)`{}': {}", pattern, shown);
+            let _ = io::stdout().flush();
+            let found = found.map(|i| self.history[i].clone());
+            match key(input) {
+                Key::Enter => return (found, true),
+                Key::Left | Key::Right | Key::Home | Key::End => return (found, false),
+                Key::Char(c) => { pattern.push(c); before = self.history.len() },
+                Key::Backspace => { pattern.pop(); before = self.history.len() },
+                Key::Ctrl(b'R') => if let Some(i) = self.history[..before].iter().rposition(|h| h.contains(&pattern)) { before = i },
+                Key::Escape | Key::Ctrl(b'G') | Key::Ctrl(b'C') | Key::Eof => {
+                    print!("\r\x1b[K{}", prompt);
+                    return (None, false);
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+// Rewrite the prompt and line, then put the cursor back where it belongs
+fn redraw(prompt: &str, line: &[char], cursor: usize) {
+    let text: String = line.iter().collect();
+    print!("\r\x1b[K{}{}", prompt, text);
+    let back = line.len() - cursor;
+    if back > 0 { print!("\x1b[{}D", back) };
+    let _ = io::stdout().flush();
+}
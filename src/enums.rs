@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use crate::error::KrEvalError;
+use crate::init::Env;
+use crate::kr::Kr;
+use crate::sym::{Domain, Sym};
+
+/*
+Enumerations of symbols against a domain, a symbol list held in a variable
+    `d$y    y as indices into d, every symbol of y must already be in d
+    `d?y    the same, adding the symbols missing from d to the end of it
+Enumerated values print and compare as the symbols they stand for
+*/
+
+// The symbols to enumerate, an enumeration is first turned back into symbols
+fn syms(y: &Kr) -> Result<Vec<Sym>, KrEvalError> {
+    match y.symbols().unwrap_or_else(|| y.clone()) {
+        Kr::S(s) => Ok(vec![s]),
+        Kr::Sv(sv) => Ok(sv),
+        Kr::NN(list) if list.is_empty() => Ok(Vec::new()),
+        _ => Err(KrEvalError::type_of(&[y])),
+    }
+}
+
+// The current symbols of a domain variable
+fn domain(e: &Env, name: Sym) -> Result<Option<Vec<Sym>>, KrEvalError> {
+    match e.get(name.name()).map(|k| k.plain().clone()) {
+        Some(Kr::Sv(sv)) => Ok(Some(sv)),
+        Some(Kr::NN(list)) if list.is_empty() => Ok(Some(Vec::new())),
+        Some(other) => Err(KrEvalError::type_of(&[&other])),
+        None => Ok(None),
+    }
+}
+
+// Where each symbol of a domain is
+fn positions(all: &[Sym]) -> HashMap<Sym, u32> {
+    all.iter().enumerate().map(|(i, s)| (*s, i as u32)).collect()
+}
+
+// Indices of the symbols in the domain, an atom gives an enumerated atom
+fn build(name: Sym, all: Vec<Sym>, positions: &HashMap<Sym, u32>, y: &Kr, symbols: &[Sym]) -> Result<Kr, KrEvalError> {
+    let indices = symbols.iter().map(|s| positions.get(s).copied().ok_or(KrEvalError::Domain)).collect::<Result<Vec<u32>, KrEvalError>>()?;
+    let domain = Domain::new(name, all);
+    match y.count() {
+        None => Ok(Kr::Enum(domain, indices[0])),
+        Some(_) => Ok(Kr::Enumv(domain, indices)),
+    }
+}
+
+pub fn enumerate(e: &Env, name: Sym, y: &Kr) -> Result<Kr, KrEvalError> {
+    let all = domain(e, name)?.ok_or(KrEvalError::NotDefined)?;
+    let positions = positions(&all);
+    build(name, all, &positions, y, &syms(y)?)
+}
+
+pub fn extend(mut e: Env, name: Sym, y: &Kr) -> (Env, Result<Kr, KrEvalError>) {
+    let symbols = match syms(y) {
+        Ok(symbols) => symbols,
+        Err(err) => return (e, Err(err)),
+    };
+    let mut all = match domain(&e, name) {
+        Ok(all) => all.unwrap_or_default(),
+        Err(err) => return (e, Err(err)),
+    };
+    let before = all.len();
+    let mut positions = positions(&all);
+    for s in &symbols {
+        if !positions.contains_key(s) {
+            positions.insert(*s, all.len() as u32);
+            all.push(*s);
+        }
+    }
+    if all.len() > before || e.get(name.name()).is_none() {
+        if let Err(err) = e.set_global(name.name(), Kr::Sv(all.clone())) { return (e, Err(err)) };
+    }
+    let res = build(name, all, &positions, y, &symbols);
+    (e, res)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+
+    fn symbols(lines: &[&str]) -> Option<crate::kr::Kr> {
+        run(lines).and_then(|x| x.symbols().or(Some(x)))
+    }
+
+    #[test]
+    fn enumeration_round_trips() {
+        assert_eq!(symbols(&["d:`a`b`c", "`d$`c`a`c"]), run(&["`c`a`c"]));
+        assert_eq!(symbols(&["d:`a`b", "`d?`b`z`a"]), run(&["`b`z`a"]));
+        assert_eq!(run(&["d:`a`b", "`d?`b`z", "d"]), run(&["`a`b`z"]));
+        assert_eq!(run(&["d:`a`b", "`d$`z"]), None);
+    }
+
+    #[test]
+    fn snapshots_that_disagree_join_as_symbols() {
+        assert_eq!(symbols(&["d:`a`b", "x:`d$`b`a", "d:`c`a`b`z", "y:`d?`z", "x,y"]), run(&["`b`a`z"]));
+        assert_eq!(symbols(&["d:`a`b", "x:`d$`b`a", "y:`d?`z", "x,y"]), run(&["`b`a`z"]));
+    }
+}
@@ -1,9 +1,41 @@
+use crate::kr::Kr;
+use crate::text::Text;
+use crate::token::Span;
 
 pub enum KrError {
+    Lex(KrLexError),
     Parse(KrParseError),
     Eval(KrEvalError),
 }
 
+pub enum KrLexError {
+    UnterminatedString,
+    InvalidNumber,
+    UnknownCharacter,
+    InvalidEscape,
+}
+
+impl DisplayError for KrLexError {
+    fn msg(&self) -> &str {
+        use KrLexError as E;
+        match self {
+            E::UnterminatedString => "unterminated string",
+            E::InvalidNumber => "invalid number",
+            E::UnknownCharacter => "unknown character",
+            E::InvalidEscape => "invalid escape in string",
+        }
+    }
+    fn code(&self) -> usize {
+        use KrLexError as E;
+        match self {
+            E::UnterminatedString => 301,
+            E::InvalidNumber => 302,
+            E::UnknownCharacter => 303,
+            E::InvalidEscape => 304,
+        }
+    }
+}
+
 pub enum KrParseError {
     UnexpectedRParen,
     UnexpectedEOF,
@@ -12,6 +44,10 @@ pub enum KrParseError {
     UnexpectedRBracket,
     MissingRBracket,
     UnexpectedSemiColon,
+    InvalidAssign,
+    UnexpectedRBrace,
+    MissingRBrace,
+    InvalidParams,
 }
 
 impl DisplayError for KrParseError {
@@ -25,6 +61,10 @@ impl DisplayError for KrParseError {
             E::UnexpectedRBracket => "unexpected ]",
             E::MissingRBracket => "missing ]",
             E::UnexpectedSemiColon => "unexpected ;",
+            E::InvalidAssign => "can only assign to a name",
+            E::UnexpectedRBrace => "unexpected }",
+            E::MissingRBrace => "missing }",
+            E::InvalidParams => "parameters must be names",
         }
     }
     fn code(&self) -> usize {
@@ -37,58 +77,192 @@ impl DisplayError for KrParseError {
             E::UnexpectedRBracket => 105,
             E::MissingRBracket => 106,
             E::UnexpectedSemiColon => 107,
+            E::InvalidAssign => 108,
+            E::UnexpectedRBrace => 109,
+            E::MissingRBrace => 110,
+            E::InvalidParams => 111,
         }
     }
 }
 
 pub enum KrEvalError {
-    Type,
+    Type(Vec<i16>),     // Type codes of the offending arguments
     NotAVerb,
     Rank,
     Length,
     Assign,
     NotDefined,
+    Domain,
+    Interrupt,
+    Reserved,
+    User(String),       // Raised by '"msg"
+    AttrFail(&'static str),     // A list without the property of the attribute applied to it
+    Memory,
+    File(String),       // A file that could not be read, and why
+    Exit,               // \\ asked to leave, carried out to the REPL which decides the exit code
+    Lex(KrLexError),    // Code run as a value by system that did not lex, with its own code
+    Parse(KrParseError),    // Or that did not parse
 }
 
 impl DisplayError for KrEvalError {
     fn msg(&self) -> &str {
         use KrEvalError as E;
         match self {
-            E::Type => "type",
+            E::Type(_) => "type",
             E::NotAVerb => "not a verb",
             E::Rank => "rank",
             E::Length => "length",
             E::Assign => "assign",
             E::NotDefined => "not defined",
+            E::Domain => "domain",
+            E::Interrupt => "interrupt",
+            E::Reserved => "reserved",
+            E::User(msg) => msg,
+            E::AttrFail(_) => "attribute",
+            E::Memory => "out of memory",
+            E::File(_) => "file",
+            E::Exit => "exit",
+            E::Lex(e) => e.msg(),
+            E::Parse(e) => e.msg(),
         }
     }
     fn code(&self) -> usize {
         use KrEvalError as E;
         match self {
-            E::Type => 201,
+            E::Type(_) => 201,
             E::NotAVerb => 202,
             E::Rank => 203,
             E::Length => 204,
             E::Assign => 205,
             E::NotDefined => 206,
+            E::Domain => 207,
+            E::Interrupt => 208,
+            E::Reserved => 209,
+            E::User(_) => 210,
+            E::AttrFail(_) => 211,
+            E::Memory => 212,
+            E::File(_) => 213,
+            E::Exit => 214,
+            E::Lex(e) => e.code(),
+            E::Parse(e) => e.code(),
         }
     }
+    fn detail(&self) -> Option<String> {
+        match self {
+            KrEvalError::Type(codes) if !codes.is_empty() => {
+                Some(codes.iter().map(|c| c.to_string()).collect::<Vec<String>>().join(" "))
+            },
+            KrEvalError::AttrFail(name) => Some(name.to_string() + "-fail"),
+            KrEvalError::File(why) => Some(why.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl KrEvalError {
+    // Type error for arguments of the wrong type
+    pub fn type_of(args: &[&Kr]) -> Self {
+        KrEvalError::Type(args.iter().map(|k| k.type_code()).collect())
+    }
+    // Whether protected evaluation may catch it, interrupts and exits always reach the REPL
+    pub fn trappable(&self) -> bool {
+        !matches!(self, KrEvalError::Interrupt | KrEvalError::Exit)
+    }
+    // Message passed to the handler of a protected evaluation
+    pub fn message(&self) -> String {
+        self.msg().to_string()
+    }
 }
 
 trait DisplayError {
     fn msg(&self) -> &str;
     fn code(&self) -> usize;
+    fn detail(&self) -> Option<String> { None }
     fn display(&self) -> String {
-        "'E".to_string() + &format!("{:0>4}", self.code()) + ": " + self.msg()
-        
+        let msg = "'E".to_string() + &format!("{:0>4}", self.code()) + ": " + self.msg();
+        match self.detail() {
+            Some(detail) => msg + ": " + &detail,
+            None => msg,
+        }
     }
 }
 
 impl KrError {
+    // The error as an eval error, for code that runs input as a value, lex and parse errors keep their code
+    pub fn into_eval(self) -> KrEvalError {
+        match self {
+            KrError::Lex(e) => KrEvalError::Lex(e),
+            KrError::Parse(e) => KrEvalError::Parse(e),
+            KrError::Eval(e) => e,
+        }
+    }
     pub fn print(&self) {
         match self {
+            KrError::Lex(e) => println!("{}", e.display()),
             KrError::Parse(e) => println!("{}", e.display()),
             KrError::Eval(e) => println!("{}", e.display()),
         }
     }
+    // The error followed by the line of source it happened on, with a caret under the span
+    pub fn print_at(&self, source: &Text, span: Span, origin: Option<&Origin>) {
+        self.print();
+        let text = String::from_utf8_lossy(&source.0);
+        let text = text.trim_end_matches(['\n', '\r']);
+        let start = span.start.min(text.len());
+        let line_start = text[..start].rfind('\n').map_or(0, |n| n + 1);
+        let line_end = text[start..].find('\n').map_or(text.len(), |n| start + n);
+        let line = text[..start].matches('\n').count() + 1;
+        match origin {
+            Some(Origin::Lambda(name, None)) => println!("  in {} line {}", name, line),
+            Some(Origin::Lambda(name, Some((path, first)))) => println!("  in {} at {}:{}", name, path, first + line - 1),
+            Some(Origin::File(path)) => println!("  at {}:{}", path, line),
+            None if text.contains('\n') => println!("  line {}", line),
+            None => {},
+        }
+        // Columns count characters rather than bytes
+        let end = span.end.clamp(start, line_end);
+        let width = text.get(start..end).map_or(1, |s| s.chars().count()).max(1);
+        println!("{}", text[line_start..line_end].trim_end());
+        println!("{}{}", " ".repeat(text[line_start..start].chars().count()), "^".repeat(width));
+    }
+}
+
+// Where an eval error happened
+// The span is relative to the input being evaluated until a lambda claims it
+#[derive(Clone, Debug)]
+pub struct Site {
+    pub span: Span,
+    pub source: Option<Text>,
+    pub origin: Option<Origin>,
+}
+
+// What the source of a site is, to name it in the message
+#[derive(Clone, Debug)]
+pub enum Origin {
+    Lambda(Text, Option<(Text, usize)>),    // Body of a function, by name, and the script and line it starts on
+    File(Text),     // Script, by path
+}
+
+impl Site {
+    pub fn new(span: Span) -> Self {
+        Site { span, source: None, origin: None }
+    }
+    // Site within a lambda's text, which starts at offset in its input
+    pub fn within(self, text: &Text, offset: usize, name: Option<Text>, file: Option<(Text, usize)>) -> Self {
+        if self.source.is_some() || self.span.start < offset { return self };
+        let span = Span { start: self.span.start - offset, end: self.span.end - offset };
+        let name = name.unwrap_or_else(|| Text::from_str("lambda"));
+        Site { span, source: Some(text.clone()), origin: Some(Origin::Lambda(name, file)) }
+    }
+    // Site within a line, for input that starts at offset in it
+    pub fn shift(self, offset: usize) -> Self {
+        if self.source.is_some() { return self };
+        Site::new(Span { start: self.span.start + offset, end: self.span.end + offset })
+    }
+    // Site within a script, for input that starts at offset in the file
+    pub fn in_file(self, text: &Text, offset: usize, path: &Text) -> Self {
+        if self.source.is_some() { return self };
+        let span = Span { start: self.span.start + offset, end: self.span.end + offset };
+        Site { span, source: Some(text.clone()), origin: Some(Origin::File(path.clone())) }
+    }
 }
\ No newline at end of file
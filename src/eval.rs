@@ -0,0 +1,214 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::KrEvalError;
+use crate::init::Env;
+use crate::kr::Kr;
+use crate::list;
+use crate::operator::Op;
+use crate::primitive::Prim;
+use crate::token::Span;
+
+pub fn eval(env: Env, ast: &Kr) -> (Env, Result<Kr, KrEvalError>) {
+    // Recursively evaluate ast
+    // A list of the form [(::); `name] means the value assigned to that name
+    // $[c;t;f] only evaluates the branch it chooses
+    // do, while and if evaluate their bodies as often as they need to
+    match ast {
+        Kr::NN(t) => {
+            let (mut env, res) = match t.len() {
+                0 => (env, Ok(Kr::Null)),
+                1 => (env, Ok(t[0].clone())),
+                n if n > 3 && matches!(&t[0], Kr::Op(op) if op.op() == Op::Cast) => eval_cond(env, &t[1..]),
+                _ if matches!(&t[0], Kr::Prim(p) if p.is_control()) => eval_control(env, &t[0], &t[1..]),
+                _ => {
+                    // Initialize a vector to store the results
+                    let mut results: Vec<Kr> = Vec::new();
+                    let mut e: Env = env;
+                    // Iterate through the elements of t, starting from the second element (index 1)
+                    for x in t {
+                        let (new_env, kr) = eval(e, x);
+                        let kr = match kr {
+                            Ok(x) => x,
+                            Err(err) => return (new_env, Err(err)),
+                        };
+                        // Append the result to the results vector
+                        results.push(kr);
+                        // Update the environment for the next iteration
+                        e = new_env;
+                    }
+                    let (first, rest) = results.split_first().expect("results should not be empty");
+                    match first.apply(e, rest) {
+                        (mut e, Err(err)) => {
+                            if e.tracing() { e.push_trace(describe_call(first, rest)) };
+                            (e, Err(err))
+                        },
+                        ok => ok,
+                    }
+                }
+            };
+            // Point errors at the verb being applied
+            if let (Err(_), Some(span)) = (&res, t.first().and_then(span_of)) { env.mark_error(span) };
+            (env, res)
+        }
+        // A lambda defined inside a function captures the locals it uses
+        Kr::Lambda(lambda) => {
+            let lambda = lambda.capture(&env);
+            (env, Ok(Kr::Lambda(lambda)))
+        },
+        other => (env, Ok(other.clone())),
+    }
+}
+
+// Items of a list shown in a backtrace, enough to fill a line
+const TRACE_ITEMS: usize = 24;
+
+// One line of a backtrace: the function and its arguments, shortened if long
+fn describe_call(f: &Kr, args: &[Kr]) -> String {
+    let f = match f {
+        Kr::Lambda(lambda) => lambda.name().map_or_else(|| lambda.to_string(), |n| n.to_string()),
+        other => brief(other),
+    };
+    let args: Vec<String> = args.iter().map(brief).collect();
+    let mut call = format!("{}[{}]", f, args.join(";"));
+    if call.chars().count() > 72 {
+        call = call.chars().take(70).collect::<String>() + "..";
+    }
+    call
+}
+
+// A value printed from only its first items, so a long list costs no more than a short one
+fn brief(x: &Kr) -> String {
+    match x {
+        Kr::NN(items) => {
+            let shown: Vec<String> = items.iter().take(TRACE_ITEMS).map(brief).collect();
+            let more = if items.len() > TRACE_ITEMS { ".." } else { "" };
+            format!("[{}{}]", shown.join(";"), more)
+        },
+        Kr::Dict(keys, values) => brief(keys) + "!" + &brief(values),
+        _ => match x.count() {
+            // Dropping the rest copies only the items kept
+            Some(n) if n > TRACE_ITEMS => match list::drop(TRACE_ITEMS as i64 - n as i64, x.plain()) {
+                Ok(head) => head.print() + "..",
+                Err(_) => x.print(),
+            },
+            _ => x.print(),
+        },
+    }
+}
+
+// Where the head of an application was written, if the parser recorded it
+fn span_of(ast: &Kr) -> Option<Span> {
+    match ast {
+        Kr::Op(op) => op.span(),
+        Kr::Prim(prim) => prim.span(),
+        Kr::NN(list) => list.first().and_then(span_of),
+        _ => None,
+    }
+}
+
+// $[c1;t1;c2;t2;...;f] evaluates the first t whose condition is true, otherwise f
+fn eval_cond(env: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let mut e: Env = env;
+    for branch in args.chunks(2) {
+        let [c, t] = branch else { return eval(e, &branch[0]) };
+        let (new_env, c) = eval(e, c);
+        e = new_env;
+        match c.and_then(|c| c.is_true()) {
+            Ok(true) => return eval(e, t),
+            Ok(false) => continue,
+            Err(err) => return (e, Err(err)),
+        }
+    }
+    (e, Ok(Kr::Null))
+}
+
+// do[n;body..] while[c;body..] if[c;body..]
+fn eval_control(env: Env, control: &Kr, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let Kr::Prim(p) = control else { unreachable!() };
+    let Some((test, body)) = args.split_first() else { return (env, Err(KrEvalError::Rank)) };
+    let (mut e, test_value) = eval(env, test);
+    let mut test_value = match test_value {
+        Ok(x) => x,
+        Err(err) => return (e, Err(err)),
+    };
+    let mut count: i64 = 0;
+    loop {
+        let again = match p.prim() {
+            Prim::Do => match test_value {
+                Kr::I(n) => Ok(count < n as i64),
+                Kr::J(n) => Ok(count < n),
+                ref other => Err(KrEvalError::type_of(&[other])),
+            },
+            Prim::While => test_value.is_true(),
+            _ => test_value.is_true().map(|t| t && count == 0),
+        };
+        match again {
+            Ok(true) => {},
+            Ok(false) => return (e, Ok(Kr::Null)),
+            Err(err) => return (e, Err(err)),
+        }
+        if take_interrupt() { return (e, Err(KrEvalError::Interrupt)) };
+        for x in body {
+            let (new_env, res) = eval(e, x);
+            e = new_env;
+            if let Err(err) = res { return (e, Err(err)) };
+        }
+        count += 1;
+        // A while condition is evaluated again before every iteration
+        if let Prim::While = p.prim() {
+            let (new_env, res) = eval(e, test);
+            e = new_env;
+            test_value = match res {
+                Ok(x) => x,
+                Err(err) => return (e, Err(err)),
+            };
+        }
+    }
+}
+
+/*
+Ctrl-C sets a flag rather than killing the process
+Loops check it on every iteration and abort with an interrupt error back to the prompt
+*/
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+pub fn install_interrupt() {
+    const SIGINT: i32 = 2;
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+    extern "C" fn on_interrupt(_: i32) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    unsafe { signal(SIGINT, on_interrupt); }
+}
+
+#[cfg(not(unix))]
+pub fn install_interrupt() {}
+
+// Forget any Ctrl-C pressed while waiting for input
+pub fn clear_interrupt() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
+
+fn take_interrupt() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
+
+// Evaluate lines in turn in a new environment, giving the value of the last, None if it fails
+// Earlier lines may fail, so tests can look at what an error left behind
+#[cfg(test)]
+pub fn run(lines: &[&str]) -> Option<Kr> {
+    let mut e = crate::init::init();
+    let mut value = None;
+    for line in lines {
+        let Ok(tokens) = crate::lex::lex(line) else { value = None; continue };
+        let Ok(ast) = crate::parse::parse(&tokens) else { value = None; continue };
+        let (new_env, res) = eval(e, &ast);
+        e = new_env;
+        value = res.ok();
+    }
+    value
+}
@@ -0,0 +1,250 @@
+use crate::error::KrEvalError;
+use crate::init::Env;
+use crate::kr::Kr;
+use crate::list;
+use crate::operator::Op;
+
+/*
+Indexing and amending lists and dictionaries
+    x[i]            items of x at i, i may be a list of indices or keys
+    x[i;j]          indexes at depth, x[i][j]
+    @[x;i;f;y]      x with x[i] replaced by f[x[i];y]       @[x;i;f] uses f[x[i]]
+    .[x;p;f;y]      same as @ but p is a path of indices at depth
+The value to amend may be a symbol naming a variable, which is updated in place
+f may be : to replace the items with y
+An error leaves the value as it was, even when some items had already been amended
+*/
+
+pub fn index(x: &Kr, i: &Kr) -> Result<Kr, KrEvalError> {
+    match (x, i) {
+        (_, Kr::Null) => Ok(x.clone()),
+        (Kr::Dict(keys, values), _) => match i.count() {
+            Some(_) => Ok(Kr::from_items(i.items().iter().map(|k| index(x, k)).collect::<Result<Vec<Kr>, KrEvalError>>()?)),
+            None => Ok(keys.find(i).and_then(|p| values.item(p)).unwrap_or_else(|| values.null_item())),
+        },
+        (_, _) if x.count().is_some() => match i.count() {
+            Some(0) => list::take(0, x),
+            Some(_) => Ok(Kr::from_items(i.items().iter().map(|k| index(x, k)).collect::<Result<Vec<Kr>, KrEvalError>>()?)),
+            None => {
+                let n = list::as_long(i).ok_or(KrEvalError::type_of(&[x, i]))?;
+                Ok(usize::try_from(n).ok().and_then(|n| x.item(n)).unwrap_or_else(|| x.null_item()))
+            },
+        },
+        (_, _) => Err(KrEvalError::type_of(&[x, i])),
+    }
+}
+
+// Index through each level of nesting in turn
+pub fn index_deep(x: &Kr, path: &[Kr]) -> Result<Kr, KrEvalError> {
+    match path {
+        [] => Ok(x.clone()),
+        [i] => index(x, i),
+        [i, rest @ ..] if i.count().is_some() => {
+            Ok(Kr::from_items(i.items().iter().map(|k| index_deep(&index(x, k)?, rest)).collect::<Result<Vec<Kr>, KrEvalError>>()?))
+        },
+        [i, rest @ ..] => index_deep(&index(x, i)?, rest),
+    }
+}
+
+// Amend a value, or the variable named by a symbol, at the given path
+// Returns the amended value, or the name for a variable
+pub fn amend(e: Env, x: &Kr, path: &[Kr], f: &Kr, y: Option<&Kr>) -> (Env, Result<Kr, KrEvalError>) {
+    match x {
+        Kr::S(name) => {
+            // A symbol names a local if there is one, otherwise a global
+            let global = e.local(name.name()).is_none();
+            let (e, res) = amend_var(e, name.name(), path, f, y, global);
+            (e, res.map(|_| x.clone()))
+        },
+        _ => {
+            let mut target = x.clone();
+            let (e, res) = amend_all(e, &mut target, path, f, y);
+            (e, res.map(|_| target))
+        },
+    }
+}
+
+// Verbs that cannot look up variables, so the variable can be moved out of the environment while they run
+fn in_place(f: &Kr) -> bool {
+    matches!(f, Kr::Op(op) if !matches!(op.op(), Op::At | Op::Dot | Op::Cast | Op::Find))
+}
+
+// Amend a variable, global for :: and otherwise local inside a function, starting from a copy of a global
+// The value is moved out while it changes when f cannot read it, otherwise a copy is amended
+pub fn amend_var(mut e: Env, name: &[u8], path: &[Kr], f: &Kr, y: Option<&Kr>, global: bool) -> (Env, Result<(), KrEvalError>) {
+    let found = if in_place(f) { e.remove(name, global) } else { e.copy(name, global) };
+    let Some((mut target, scope)) = found else { return (e, Err(KrEvalError::NotDefined)) };
+    let (mut e, res) = amend_all(e, &mut target, path, f, y);
+    // A failed amend of a copy has nothing to put back
+    if res.is_ok() || in_place(f) { e.restore(name, target, scope) };
+    (e, res)
+}
+
+// A change made while amending, undone if a later item fails
+enum Undo {
+    Item(Vec<Kr>, Kr),          // The item at a path held this value
+    Length(Vec<Kr>, usize),     // The list at a path had this many items before an append
+    Key(Vec<Kr>),               // The dictionary at a path gained a key at the end
+}
+
+// Amend at a path, putting back everything that changed if any item fails
+fn amend_all(e: Env, target: &mut Kr, path: &[Kr], f: &Kr, y: Option<&Kr>) -> (Env, Result<(), KrEvalError>) {
+    let mut undo = Vec::new();
+    let (e, res) = amend_path(e, target, path, f, y, &mut Vec::new(), &mut undo);
+    if res.is_err() {
+        for change in undo.into_iter().rev() { revert(target, change) };
+    }
+    (e, res)
+}
+
+// Apply g to the item at a path
+fn visit(target: &mut Kr, path: &[Kr], g: &mut dyn FnMut(&mut Kr)) {
+    let [i, rest @ ..] = path else { return g(target) };
+    if let Ok(mut item) = take_at(target, i) {
+        visit(&mut item, rest, g);
+        let _ = put_at(target, i, item);
+    }
+}
+
+fn revert(target: &mut Kr, change: Undo) {
+    match change {
+        Undo::Item(path, old) => {
+            let mut old = Some(old);
+            visit(target, &path, &mut |t| if let Some(old) = old.take() { *t = old });
+        },
+        Undo::Length(path, n) => visit(target, &path, &mut |t| {
+            let items = t.items();
+            *t = Kr::from_items(items[..n.min(items.len())].to_vec());
+        }),
+        Undo::Key(path) => visit(target, &path, &mut |t| {
+            if let Kr::Dict(keys, values) = t {
+                let n = keys.count().unwrap_or(1) as i64 - 1;
+                if let (Ok(k), Ok(v)) = (list::take(n, keys), list::take(n, values)) { **keys = k; **values = v };
+            }
+        }),
+    }
+}
+
+// at is the path to target, recorded with each change so it can be undone
+fn amend_path(e: Env, target: &mut Kr, path: &[Kr], f: &Kr, y: Option<&Kr>, at: &mut Vec<Kr>, undo: &mut Vec<Undo>) -> (Env, Result<(), KrEvalError>) {
+    let [i, rest @ ..] = path else {
+        // x,:y appends in place rather than copying x
+        if let (Kr::Op(op), Some(y)) = (f, y) {
+            if op.op() == Op::Join {
+                match target.count() {
+                    Some(n) => undo.push(Undo::Length(at.clone(), n)),
+                    None => undo.push(Undo::Item(at.clone(), target.clone())),
+                }
+                target.append(y);
+                return (e, Ok(()));
+            }
+        }
+        let (e, res) = update(e, target, f, y);
+        return match res {
+            Ok(new) => {
+                undo.push(Undo::Item(at.clone(), std::mem::replace(target, new)));
+                (e, Ok(()))
+            },
+            Err(err) => (e, Err(err)),
+        };
+    };
+    let indices: Vec<Kr> = match (i, &*target) {
+        (Kr::Null, Kr::Dict(keys, _)) => keys.items(),
+        (Kr::Null, _) => (0..target.count().unwrap_or(0) as i64).map(Kr::J).collect(),
+        (_, _) => i.items(),
+    };
+    // A list of indices takes the matching items of a list y
+    let spread = (i.count().is_some() || matches!(i, Kr::Null)) && y.and_then(|y| y.count()) == Some(indices.len());
+    let mut e = e;
+    for (k, idx) in indices.iter().enumerate() {
+        let yk = if spread { y.and_then(|y| y.item(k)) } else { y.cloned() };
+        let mut item = match take_at(target, idx) {
+            Ok(item) => item,
+            Err(err) => return (e, Err(err)),
+        };
+        // A new key is undone after the changes within its value, which need it to be found
+        if matches!(target, Kr::Dict(keys, _) if keys.find(idx).is_none()) { undo.push(Undo::Key(at.clone())) };
+        at.push(idx.clone());
+        let (new_env, res) = amend_path(e, &mut item, rest, f, yk.as_ref(), at, undo);
+        at.pop();
+        e = new_env;
+        // A failed item still goes back, so undoing the changes within it finds it
+        let put = put_at(target, idx, item);
+        if let Err(err) = res.and(put) { return (e, Err(err)) };
+    }
+    (e, Ok(()))
+}
+
+// New value for an item, : or :: replaces it with y
+fn update(e: Env, old: &Kr, f: &Kr, y: Option<&Kr>) -> (Env, Result<Kr, KrEvalError>) {
+    match (f, y) {
+        (Kr::Op(op), Some(y)) if matches!(op.op(), Op::Assign | Op::AssignGlobal) => (e, Ok(y.clone())),
+        (_, Some(y)) => f.apply(e, &[old.clone(), y.clone()]),
+        (_, None) => f.apply(e, std::slice::from_ref(old)),
+    }
+}
+
+// Move out the item at an index or key, a missing key gives a null
+fn take_at(target: &mut Kr, idx: &Kr) -> Result<Kr, KrEvalError> {
+    match target {
+        Kr::Dict(keys, values) => match keys.find(idx) {
+            Some(p) => values.take_item(p).ok_or(KrEvalError::Length),
+            None => Ok(values.null_item()),
+        },
+        _ if target.count().is_some() => {
+            let n = list::as_long(idx).ok_or(KrEvalError::type_of(&[target, idx]))?;
+            usize::try_from(n).ok().and_then(|n| target.take_item(n)).ok_or(KrEvalError::Length)
+        },
+        _ => Err(KrEvalError::type_of(&[target, idx])),
+    }
+}
+
+// Store an item at an index, a new key is added to the end of a dictionary
+fn put_at(target: &mut Kr, idx: &Kr, item: Kr) -> Result<(), KrEvalError> {
+    match target {
+        Kr::Dict(keys, values) => match keys.find(idx) {
+            Some(p) => values.set_item(p, item),
+            None => {
+                keys.push_item(idx.clone());
+                values.push_item(item);
+                Ok(())
+            },
+        },
+        _ => {
+            let n = list::as_long(idx).ok_or(KrEvalError::type_of(&[target, idx]))?;
+            target.set_item(n as usize, item)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+    use crate::kr::Kr;
+
+    #[test]
+    fn failed_amend_changes_nothing() {
+        assert_eq!(run(&["x:til 3", "x[til 5]:9"]), None);
+        assert_eq!(run(&["x:til 3", "x[til 5]:9", "x"]), Some(Kr::Jv(vec![0, 1, 2])));
+        assert_eq!(run(&["x:til 3", "x[0,1]+:enlist[1;`a]", "x"]), Some(Kr::Jv(vec![0, 1, 2])));
+        assert_eq!(run(&["d:`a`b!1,2", "d[enlist[`z;`a]]:enlist[1;`q]", "key d"]), run(&["`a`b"]));
+    }
+
+    // f can read the variable it amends
+    #[test]
+    fn amended_variable_is_readable() {
+        assert_eq!(run(&["y:enlist[`y;1]", "@[`y;0;value]", "y"]), run(&["enlist[enlist[`y;1];1]"]));
+        assert_eq!(run(&["y:10,20", "@[`y;0;{[a] y 1}]", "y"]), Some(Kr::Jv(vec![20, 20])));
+    }
+
+    #[test]
+    fn function_amends_locals() {
+        assert_eq!(run(&["v:til 3", "f:{[a] v[0]:99; v,:7; v}", "f 1"]), Some(Kr::Jv(vec![99, 1, 2, 7])));
+        assert_eq!(run(&["v:til 3", "f:{[a] v[0]:99; v,:7; a}", "f 1", "v"]), Some(Kr::Jv(vec![0, 1, 2])));
+    }
+
+    #[test]
+    fn function_amends_globals_with_double_colon() {
+        assert_eq!(run(&["g:{[a] w[0]::5; a}", "w:til 2", "g 1", "w"]), Some(Kr::Jv(vec![5, 1])));
+    }
+}
@@ -1,20 +1,24 @@
 use std::collections::HashMap;
-use crate::error::KrEvalError;
+use crate::error::{KrEvalError, Site};
 use crate::kr::Kr;
 
 use crate::operator::{Operator, Op};
 use crate::primitive::{Primitive, Prim};
+use crate::random::{Rng, DEFAULT_SEED};
+use crate::sym::Sym;
 use crate::text::Text;
+use crate::token::Span;
 
+// Built-ins live in the reserved .kr namespace
 macro_rules! insert_operator {
-    ($env:expr, $($text:expr => $op:expr),*) => {
-        $($env.var.insert(Text::from_str($text), Kr::Op(Operator::new($op)));)*
+    ($builtins:expr, $($text:expr => $op:expr),*) => {
+        $(put_path(&mut $builtins, &[Text::from_str($text)], Kr::Op(Operator::new($op)));)*
     };
 }
 
 macro_rules! insert_primitive {
-    ($env:expr, $($text:expr => $prim:expr),*) => {
-        $($env.var.insert(Text::from_str($text), Kr::Prim(Primitive::new($prim)));)*
+    ($builtins:expr, $($text:expr => $prim:expr),*) => {
+        $(put_path(&mut $builtins, &[Text::from_str($text)], Kr::Prim(Primitive::new($prim)));)*
     };
 }
 
@@ -23,44 +27,370 @@ pub fn init() -> Env {
     env.var.insert(Text::from_str("one"), Kr::J(1));
     env.var.insert(Text::from_str("two"), Kr::J(2));
     env.var.insert(Text::from_str("alph"), Kr::C(b'a'));
+    let mut builtins = namespace();
     insert_primitive!(
-        env,
+        builtins,
         "first" => Prim::First,
         "last" => Prim::Last,
         "til" => Prim::Til,
-        "value" => Prim::Value
+        "enlist" => Prim::Enlist,
+        "value" => Prim::Value,
+        "reverse" => Prim::Reverse,
+        "rotate" => Prim::Rotate,
+        "raze" => Prim::Raze,
+        "flip" => Prim::Flip,
+        "string" => Prim::String,
+        "type" => Prim::Type,
+        "key" => Prim::Key,
+        "trp" => Prim::Trap,
+        "like" => Prim::Like,
+        "ss" => Prim::Ss,
+        "ssr" => Prim::Ssr,
+        "vs" => Prim::Vs,
+        "sv" => Prim::Sv,
+        "upper" => Prim::Upper,
+        "lower" => Prim::Lower,
+        "trim" => Prim::Trim,
+        "ltrim" => Prim::Ltrim,
+        "rtrim" => Prim::Rtrim,
+        "attr" => Prim::Attr,
+        "in" => Prim::In,
+        "bin" => Prim::Bin,
+        "sqrt" => Prim::Sqrt,
+        "exp" => Prim::Exp,
+        "log" => Prim::Log,
+        "xexp" => Prim::Xexp,
+        "xlog" => Prim::Xlog,
+        "abs" => Prim::Abs,
+        "neg" => Prim::Neg,
+        "signum" => Prim::Signum,
+        "floor" => Prim::Floor,
+        "ceiling" => Prim::Ceiling,
+        "reciprocal" => Prim::Reciprocal,
+        "mod" => Prim::Mod,
+        "div" => Prim::Div,
+        "sin" => Prim::Sin,
+        "cos" => Prim::Cos,
+        "tan" => Prim::Tan,
+        "asin" => Prim::Asin,
+        "acos" => Prim::Acos,
+        "atan" => Prim::Atan,
+        "wsum" => Prim::Wsum,
+        "wavg" => Prim::Wavg,
+        "system" => Prim::System
     );
     insert_operator!(
-        env,
+        builtins,
         "+" => Op::Addition,
         "-" => Op::Subtraction,
         "*" => Op::Multiplication,
         "%" => Op::Division,
         ":" => Op::Assign,
-        "," => Op::Join
+        "," => Op::Join,
+        "#" => Op::Take,
+        "_" => Op::Drop,
+        "$" => Op::Cast,
+        "!" => Op::Dict,
+        "@" => Op::At,
+        "." => Op::Dot,
+        "=" => Op::Equal,
+        "<" => Op::Less,
+        ">" => Op::Greater,
+        "?" => Op::Find
     );
+    // Names are resolved through a hash of the same built-ins, the .kr dictionary is what code sees
+    if let Kr::Dict(k, v) = &builtins {
+        for (key, value) in k.items().into_iter().zip(v.items()) {
+            if let Kr::S(s) = key { env.builtins.insert(s.text(), value); };
+        }
+    }
+    env.var.insert(Text::from_str(BUILTINS), builtins);
     env
 }
 
+/*
+Names
+    x           a local of the current function, a built-in, then x in the current context, then a global
+    .ns.x       x in the namespace .ns, a dictionary held in the global .ns
+Namespaces nest, .a.b.c is .a[`b][`c], and are created by assigning into them
+\d .ns makes .ns the current context, so x:1 at the top level sets .ns.x
+Built-ins are held in .kr and cannot be assigned
+*/
+
+const BUILTINS: &str = ".kr";
+
+// Where a variable lives, a global is held under its fully qualified name
+#[derive(Clone, PartialEq)]
+pub enum Scope {
+    Local,
+    Global(Text),
+}
+
 #[derive(Clone)]
 pub struct Env {
-    // For now env is a hashmap of names to Kr variables
-    // Later it can become a kr_tree
+    // Globals, each namespace is a dictionary held under its top level name
     pub var: HashMap<Text, Kr>,
+    // Local variables of each function being evaluated, innermost last
+    // Only the innermost frame is visible, so callers' locals are never touched
+    frames: Vec<HashMap<Text, Kr>>,
+    // Current namespace set by \d, empty for the root
+    context: Text,
+    // Where the error being returned happened, set by the innermost failing application
+    site: Option<Site>,
+    // Calls that failed on the way out from an error, innermost first
+    trace: Vec<String>,
+    // Built-ins by name, also held in .kr
+    builtins: HashMap<Text, Kr>,
+    // Whether failed calls are added to the trace, off while @ and . trap errors since they discard it
+    tracing: bool,
+    // Print the trace after an error, toggled with \e
+    pub show_trace: bool,
+    // Generator for ?, reseeded with \S
+    pub rng: Rng,
+    // Rows and columns of the console, values printed at the prompt are cut to fit, set with \c
+    pub console: (usize, usize),
     pub opts: Vec<String>,
 }
 
 impl Env {
     pub fn new() -> Self {
         let opts: Vec<String> = std::env::args().collect();
-        Env { var: HashMap::new(), opts }
+        Env { var: HashMap::new(), frames: Vec::new(), context: Text::from_str(""), site: None, trace: Vec::new(), builtins: HashMap::new(), tracing: true, show_trace: true, rng: Rng::new(DEFAULT_SEED), console: (25, 80), opts }
     }
     pub fn val(&self, v: &Kr) -> Result<Kr, KrEvalError> {
         match v {
             Kr::S(s) => {
-                self.var.get(s).ok_or(KrEvalError::NotDefined).cloned()
+                self.get(s.name()).ok_or(KrEvalError::NotDefined)
+            },
+            _ => Err(KrEvalError::type_of(&[v])),
+        }
+    }
+    pub fn get(&self, name: &[u8]) -> Option<Kr> {
+        if is_qualified(name) { return self.get_global(name) };
+        self.local(name).cloned()
+            .or_else(|| self.builtin(name))
+            .or_else(|| if self.context.is_empty() { None } else { self.get_global(&self.qualify(name)) })
+            .or_else(|| self.var.get(name).cloned())
+    }
+    pub fn local(&self, name: &[u8]) -> Option<&Kr> {
+        self.frames.last().and_then(|frame| frame.get(name))
+    }
+    fn builtin(&self, name: &[u8]) -> Option<Kr> {
+        self.builtins.get(name).cloned()
+    }
+    // Value of a fully qualified name
+    fn get_global(&self, name: &[u8]) -> Option<Kr> {
+        let (root, keys) = split(name);
+        self.var.get(&root).and_then(|kr| lookup(kr, &keys))
+    }
+    // Assignment inside a function is local to it, otherwise global
+    pub fn set(&mut self, name: &[u8], value: Kr) -> Result<(), KrEvalError> {
+        if is_qualified(name) { return self.set_global(name, value) };
+        if self.builtins.contains_key(name) { return Err(KrEvalError::Reserved) };
+        match self.frames.last_mut() {
+            Some(frame) => { insert(frame, name, value); Ok(()) },
+            None => self.set_global(name, value),
+        }
+    }
+    pub fn set_global(&mut self, name: &[u8], value: Kr) -> Result<(), KrEvalError> {
+        if !is_qualified(name) && self.builtins.contains_key(name) { return Err(KrEvalError::Reserved) };
+        if !is_qualified(name) && self.context.is_empty() { insert(&mut self.var, name, value); return Ok(()) };
+        let name = self.qualify(name);
+        let (root, keys) = split(&name);
+        if root.0 == BUILTINS.as_bytes() { return Err(KrEvalError::Reserved) };
+        if keys.is_empty() {
+            self.var.insert(root, value);
+        } else {
+            put_path(self.var.entry(root).or_insert_with(namespace), &keys, value);
+        }
+        Ok(())
+    }
+    // Where an amended variable lives: inside a function a plain name is local unless global is set,
+    // the global it names is only read to give the local its first value
+    fn scope_of(&self, name: &[u8], global: bool) -> Option<Scope> {
+        if !global && !is_qualified(name) && !self.frames.is_empty() {
+            return if self.builtins.contains_key(name) { None } else { Some(Scope::Local) };
+        }
+        // The same search as get, but only global names can be amended
+        let qualified = match is_qualified(name) {
+            true => Text::from_slice(name),
+            false if self.get_global(&self.qualify(name)).is_some() => self.qualify(name),
+            false => Text::from_slice(name),
+        };
+        if split(&qualified).0 .0 == BUILTINS.as_bytes() { return None };
+        Some(Scope::Global(qualified))
+    }
+    // Move a variable out of wherever it lives, to be put back with restore
+    pub fn remove(&mut self, name: &[u8], global: bool) -> Option<(Kr, Scope)> {
+        let scope = self.scope_of(name, global)?;
+        let value = match &scope {
+            Scope::Local => match self.frames.last_mut().and_then(|frame| frame.remove(name)) {
+                Some(value) => value,
+                None => self.get(name)?,
+            },
+            Scope::Global(qualified) => {
+                let (root, keys) = split(qualified);
+                match keys[..] {
+                    [] => self.var.remove(&root)?,
+                    _ => take_path(self.var.get_mut(&root)?, &keys)?,
+                }
             },
-            _ => Err(KrEvalError::Type),
+        };
+        Some((value, scope))
+    }
+    // A copy of a variable and where it lives, for amending while the variable can still be read
+    pub fn copy(&self, name: &[u8], global: bool) -> Option<(Kr, Scope)> {
+        let scope = self.scope_of(name, global)?;
+        let value = match &scope {
+            Scope::Local => self.get(name)?,
+            Scope::Global(qualified) => self.get_global(qualified)?,
+        };
+        Some((value, scope))
+    }
+    pub fn restore(&mut self, name: &[u8], value: Kr, scope: Scope) {
+        let _ = match scope {
+            Scope::Local => self.set(name, value),
+            Scope::Global(qualified) => self.set_global(&qualified, value),
+        };
+    }
+    pub fn push_frame(&mut self, frame: HashMap<Text, Kr>) {
+        self.frames.push(frame);
+    }
+    pub fn pop_frame(&mut self) {
+        self.frames.pop();
+    }
+    pub fn context(&self) -> &Text {
+        &self.context
+    }
+    // Change the current context, a namespace name such as .ns or . for the root
+    pub fn set_context(&mut self, name: &Text) -> Result<(), KrEvalError> {
+        match &name.0[..] {
+            b"." | b"" => self.context = Text::from_str(""),
+            [b'.', rest @ ..] if !rest.is_empty() && !rest.contains(&b'.') => self.context = name.clone(),
+            _ => return Err(KrEvalError::Domain),
         }
+        Ok(())
+    }
+    // Record where an error happened, unless a more specific site is already known
+    pub fn mark_error(&mut self, span: Span) {
+        if self.site.is_none() { self.site = Some(Site::new(span)) };
+    }
+    pub fn locate_error(&mut self, f: impl FnOnce(Site) -> Site) {
+        self.site = self.site.take().map(f);
     }
-}
\ No newline at end of file
+    pub fn take_site(&mut self) -> Option<Site> {
+        self.site.take()
+    }
+    pub fn push_trace(&mut self, call: String) {
+        self.trace.push(call);
+    }
+    pub fn take_trace(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.trace)
+    }
+    pub fn tracing(&self) -> bool {
+        self.tracing
+    }
+    // Turn tracing on or off, giving the previous setting to restore
+    pub fn set_tracing(&mut self, on: bool) -> bool {
+        std::mem::replace(&mut self.tracing, on)
+    }
+    // Globals held in a namespace, or in the root for an empty name, sorted by name
+    // Namespaces themselves are not included
+    pub fn globals(&self, ns: &Text) -> Vec<(Text, Kr)> {
+        let mut found: Vec<(Text, Kr)> = match ns.len() {
+            0 => self.var.iter()
+                .filter(|(name, _)| !is_qualified(name))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            _ => match self.get_global(ns) {
+                Some(Kr::Dict(k, v)) => k.items().into_iter().zip(v.items())
+                    .filter_map(|(key, value)| match key {
+                        Kr::S(s) => Some((s.text(), value)),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            },
+        };
+        found.sort_by(|a, b| a.0 .0.cmp(&b.0 .0));
+        found
+    }
+    // Name of an unqualified global in the current context
+    fn qualify(&self, name: &[u8]) -> Text {
+        if is_qualified(name) || self.context.is_empty() { return Text::from_slice(name) };
+        Text::new([&self.context[..], b".", name].concat())
+    }
+}
+
+fn is_qualified(name: &[u8]) -> bool {
+    name.first() == Some(&b'.')
+}
+
+// Store a value under a name, the name is only copied the first time it is stored
+fn insert(vars: &mut HashMap<Text, Kr>, name: &[u8], value: Kr) {
+    match vars.get_mut(name) {
+        Some(slot) => *slot = value,
+        None => { vars.insert(Text::from_slice(name), value); },
+    }
+}
+
+// Split .a.b.c into the global .a and the keys `b`c
+fn split(name: &[u8]) -> (Text, Vec<Text>) {
+    if !is_qualified(name) { return (Text::from_slice(name), Vec::new()) };
+    let mut parts = name[1..].split(|c| *c == b'.');
+    let root = Text::new([b".", parts.next().unwrap_or_default()].concat());
+    (root, parts.map(Text::from_slice).collect())
+}
+
+// An empty namespace
+fn namespace() -> Kr {
+    Kr::Dict(Box::new(Kr::Sv(Vec::new())), Box::new(Kr::NN(Vec::new())))
+}
+
+fn lookup(kr: &Kr, keys: &[Text]) -> Option<Kr> {
+    match (kr, keys) {
+        (_, []) => Some(kr.clone()),
+        (Kr::Dict(k, v), [key, rest @ ..]) => {
+            let p = k.find(&Kr::S(Sym::from_text(key)))?;
+            lookup(&v.item(p)?, rest)
+        },
+        _ => None,
+    }
+}
+
+// Store a value at a key path, creating namespaces as needed
+fn put_path(kr: &mut Kr, keys: &[Text], value: Kr) {
+    let [key, rest @ ..] = keys else { *kr = value; return };
+    if !matches!(kr, Kr::Dict(_, _)) { *kr = namespace() };
+    let Kr::Dict(k, v) = kr else { unreachable!() };
+    match k.find(&Kr::S(Sym::from_text(key))) {
+        Some(p) => {
+            let mut item = v.take_item(p).unwrap_or(Kr::Null);
+            put_path(&mut item, rest, value);
+            if v.set_item(p, item.clone()).is_err() {
+                // A typed vector of values becomes a general list to hold the new item
+                let mut items = v.items();
+                items[p] = item;
+                **v = Kr::NN(items);
+            }
+        },
+        None => {
+            let mut item = Kr::Null;
+            put_path(&mut item, rest, value);
+            k.push_item(Kr::S(Sym::from_text(key)));
+            v.push_item(item);
+        },
+    }
+}
+
+// Move the value at a key path out of a namespace
+fn take_path(kr: &mut Kr, keys: &[Text]) -> Option<Kr> {
+    let [key, rest @ ..] = keys else { return Some(std::mem::replace(kr, Kr::Null)) };
+    let Kr::Dict(k, v) = kr else { return None };
+    let p = k.find(&Kr::S(Sym::from_text(key)))?;
+    match v.as_mut() {
+        Kr::NN(items) => take_path(items.get_mut(p)?, rest),
+        _ if rest.is_empty() => v.item(p),
+        _ => None,
+    }
+}
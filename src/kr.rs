@@ -1,69 +1,445 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::attr::Attribute;
 use crate::error::KrEvalError;
 use crate::operator::Operator;
-use crate::text::Text;
+use crate::index;
 use crate::init::Env;
+use crate::lambda::Lambda;
+use crate::list;
 use crate::primitive::Primitive;
+use crate::sym::{Domain, Sym};
+
+// Signature shared by every built in verb
+pub type KrFn = fn(Env, &[Kr]) -> (Env, Result<Kr, KrEvalError>);
+
+// Null values for types without a natural null
+// Reals and floats use NaN, characters use a space and symbols are empty
+pub const NULL_I: i32 = i32::MIN;
+pub const NULL_J: i64 = i64::MIN;
 
 #[derive(Clone, Debug)]
 pub enum Kr {
-    // B(bool),Bv(Vec<bool>),   // Boolean
+    B(bool), Bv(Vec<bool>),     // Boolean
     I(i32), Iv(Vec<i32>),       // Integer
     J(i64), Jv(Vec<i64>),       // Long 
     E(f32), Ev(Vec<f32>),       // Real
     F(f64), Fv(Vec<f64>),       // Float
     C(u8),  Cv(Vec<u8>),        // Character
-    S(Text),
+    S(Sym), Sv(Vec<Sym>),       // Symbol, interned
+    Enum(Domain, u32), Enumv(Domain, Vec<u32>),   // Enumerated symbols, indices into a domain
     Op(Operator),               // Operator
     Prim(Primitive),            // Primitive
+    Lambda(Lambda),             // User defined function
     Null,                       // Null
     NN(Vec<Kr>),                // General list of variables
+    Dict(Box<Kr>, Box<Kr>),     // Dictionary of keys to values, both lists of equal count
+    Attr(Attribute, Box<Kr>),   // List with a sorted, unique, parted or grouped attribute
 }
 
 impl Kr {
     pub fn print(&self) -> String {
         match self {
-            Kr::I(n) => n.to_string(),
-            Kr::J(n) => n.to_string(),
-            Kr::E(n) => n.to_string(),
-            Kr::F(n) => n.to_string(),
-            Kr::C(c) => "\"".to_string() + &c.to_string().to_owned() + "\"",
+            Kr::B(b) => (*b as u8).to_string() + "b",
+            Kr::I(n) => print_int(*n),
+            Kr::J(n) => print_long(*n),
+            Kr::E(n) => print_float(*n as f64),
+            Kr::F(n) => print_float(*n),
+            Kr::C(c) => "\"".to_string() + &escape(&[*c]) + "\"",
             Kr::S(sym) => { "`".to_string() + &sym.to_string()},
             Kr::Null => "(::)".to_string(),
-            Kr::Iv(iv) => vec_to_string(iv, " ", "", ""),
-            Kr::Jv(jv) => vec_to_string(jv, " ", "", ""),
-            Kr::Ev(ev) => vec_to_string(ev, " ", "", ""),
-            Kr::Fv(fv) => vec_to_string(fv, " ", "", ""),
-            Kr::Cv(cv) => {"\"".to_owned() + &String::from_utf8(cv.to_vec()).unwrap() + "\""},
+            Kr::Bv(bv) => bv.iter().map(|b| (*b as u8).to_string()).collect::<String>() + "b",
+            Kr::Iv(iv) => vec_to_string(&iv.iter().map(|n| print_int(*n)).collect::<Vec<_>>(), " ", "", ""),
+            Kr::Jv(jv) => vec_to_string(&jv.iter().map(|n| print_long(*n)).collect::<Vec<_>>(), " ", "", ""),
+            Kr::Ev(ev) => vec_to_string(&ev.iter().map(|n| print_float(*n as f64)).collect::<Vec<_>>(), " ", "", ""),
+            Kr::Fv(fv) => vec_to_string(&fv.iter().map(|n| print_float(*n)).collect::<Vec<_>>(), " ", "", ""),
+            Kr::Sv(sv) => vec_to_string(sv, "`", "`", ""),
+            Kr::Enum(_, _) | Kr::Enumv(_, _) => self.symbols().unwrap_or(Kr::Null).print(),
+            Kr::Attr(a, x) => "`".to_string() + a.attr().name() + "#" + &x.print(),
+            Kr::Cv(cv) => {"\"".to_owned() + &escape(cv) + "\""},
             Kr::Op(op) => op.to_string(),
             Kr::Prim(prim) => prim.display(),
+            Kr::Lambda(lambda) => lambda.to_string(),
             Kr::NN(kl) => { // vec_to_string(kl, "\n ")
                 let mut output = String::new();
                 output.push('[');
                 for k in kl {
                     output.push_str(&k.print());
-                    output.push_str(";");
+                    output.push(';');
                 }
-                output.pop();
+                if !kl.is_empty() { output.pop(); };
                 output.push(']');
                 output
             }
+            Kr::Dict(keys, values) => keys.print() + "!" + &values.print(),
         }
     }
 }
 
 impl Kr {
+    // Type codes follow other array languages: negative for atoms, positive for vectors,
+    // 0 for general lists, 99 for dictionaries and 100 and above for functions
+    pub fn type_code(&self) -> i16 {
+        match self {
+            Kr::NN(_) => 0,
+            Kr::Dict(_, _) => 99,
+            Kr::Lambda(_) => 100,
+            Kr::B(_) => -1, Kr::Bv(_) => 1,
+            Kr::I(_) => -6, Kr::Iv(_) => 6,
+            Kr::J(_) => -7, Kr::Jv(_) => 7,
+            Kr::E(_) => -8, Kr::Ev(_) => 8,
+            Kr::F(_) => -9, Kr::Fv(_) => 9,
+            Kr::C(_) => -10, Kr::Cv(_) => 10,
+            Kr::S(_) => -11, Kr::Sv(_) => 11,
+            Kr::Enum(_, _) => -20, Kr::Enumv(_, _) => 20,
+            Kr::Attr(_, x) => x.type_code(),
+            Kr::Null | Kr::Prim(_) => 101,
+            Kr::Op(_) => 102,
+        }
+    }
+
     pub fn apply(&self, env: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
         match self {
             Kr::Op(op) => op.apply(env, args),
             Kr::Prim(prim) => prim.apply(env, args),
+            Kr::Lambda(lambda) => lambda.apply(env, args),
+            Kr::Attr(_, x) => x.apply(env, args),
+            // Lists and dictionaries are indexed by their arguments
+            Kr::Dict(_, _) => (env, index::index_deep(self, args)),
+            _ if self.count().is_some() => (env, index::index_deep(self, args)),
             _ => (env, Err(KrEvalError::NotAVerb)),
         }
     }
 }
 
-fn vec_to_string<T>(v: &Vec<T>, delim: &str, start: &str, end: &str) -> String 
+impl Kr {
+    // Number of items in a list, atoms have no count
+    pub fn count(&self) -> Option<usize> {
+        match self {
+            Kr::Bv(v) => Some(v.len()),
+            Kr::Iv(v) => Some(v.len()),
+            Kr::Jv(v) => Some(v.len()),
+            Kr::Ev(v) => Some(v.len()),
+            Kr::Fv(v) => Some(v.len()),
+            Kr::Cv(v) => Some(v.len()),
+            Kr::Sv(v) => Some(v.len()),
+            Kr::Enumv(_, v) => Some(v.len()),
+            Kr::NN(v) => Some(v.len()),
+            Kr::Attr(_, x) => x.count(),
+            _ => None,
+        }
+    }
+
+    // The i-th item of a list as a Kr atom (or Kr value for general lists)
+    pub fn item(&self, i: usize) -> Option<Kr> {
+        match self {
+            Kr::Bv(v) => v.get(i).map(|x| Kr::B(*x)),
+            Kr::Iv(v) => v.get(i).map(|x| Kr::I(*x)),
+            Kr::Jv(v) => v.get(i).map(|x| Kr::J(*x)),
+            Kr::Ev(v) => v.get(i).map(|x| Kr::E(*x)),
+            Kr::Fv(v) => v.get(i).map(|x| Kr::F(*x)),
+            Kr::Cv(v) => v.get(i).map(|x| Kr::C(*x)),
+            Kr::Sv(v) => v.get(i).map(|x| Kr::S(*x)),
+            Kr::Enumv(d, v) => v.get(i).map(|x| Kr::Enum(d.clone(), *x)),
+            Kr::NN(v) => v.get(i).cloned(),
+            Kr::Attr(_, x) => x.item(i),
+            _ => None,
+        }
+    }
+
+    // Null atom of the same type as the items of a list
+    pub fn null_item(&self) -> Kr {
+        match self {
+            Kr::Bv(_) => Kr::B(false),
+            Kr::Iv(_) => Kr::I(NULL_I),
+            Kr::Jv(_) => Kr::J(NULL_J),
+            Kr::Ev(_) => Kr::E(f32::NAN),
+            Kr::Fv(_) => Kr::F(f64::NAN),
+            Kr::Cv(_) => Kr::C(b' '),
+            Kr::Sv(_) => Kr::S(Sym::empty()),
+            Kr::Enumv(d, _) => Kr::Enum(d.clone(), u32::MAX),
+            Kr::Attr(_, x) => x.null_item(),
+            _ => Kr::Null,
+        }
+    }
+
+    // Move the i-th item out of a list, general lists are left holding a null in its place
+    pub fn take_item(&mut self, i: usize) -> Option<Kr> {
+        self.drop_attr();
+        match self {
+            Kr::NN(v) => v.get_mut(i).map(|x| std::mem::replace(x, Kr::Null)),
+            _ => self.item(i),
+        }
+    }
+
+    // Replace the i-th item of a list, which must have the same type as a typed vector
+    pub fn set_item(&mut self, i: usize, new: Kr) -> Result<(), KrEvalError> {
+        if i >= self.count().unwrap_or(0) { return Err(KrEvalError::Length) };
+        self.drop_attr();
+        if self.disagrees(&new) {
+            *self = self.symbols().unwrap();
+            return self.set_item(i, new.symbols().unwrap());
+        }
+        match (self, new) {
+            (Kr::Bv(v), Kr::B(a)) => v[i] = a,
+            (Kr::Iv(v), Kr::I(a)) => v[i] = a,
+            (Kr::Jv(v), Kr::J(a)) => v[i] = a,
+            (Kr::Ev(v), Kr::E(a)) => v[i] = a,
+            (Kr::Fv(v), Kr::F(a)) => v[i] = a,
+            (Kr::Cv(v), Kr::C(a)) => v[i] = a,
+            (Kr::Sv(v), Kr::S(a)) => v[i] = a,
+            (Kr::Enumv(d, v), Kr::Enum(d2, a)) if d.name() == d2.name() => { *d = d.latest(&d2).unwrap_or(d2); v[i] = a },
+            (Kr::NN(v), a) => v[i] = a,
+            (x, a) => return Err(KrEvalError::type_of(&[x, &a])),
+        };
+        Ok(())
+    }
+
+    // Append an item to a list, a typed vector becomes a general list if the type differs
+    pub fn push_item(&mut self, new: Kr) {
+        self.drop_attr();
+        if self.disagrees(&new) {
+            *self = self.symbols().unwrap();
+            return self.push_item(new.symbols().unwrap());
+        }
+        match (&mut *self, new) {
+            (Kr::Bv(v), Kr::B(a)) => v.push(a),
+            (Kr::Iv(v), Kr::I(a)) => v.push(a),
+            (Kr::Jv(v), Kr::J(a)) => v.push(a),
+            (Kr::Ev(v), Kr::E(a)) => v.push(a),
+            (Kr::Fv(v), Kr::F(a)) => v.push(a),
+            (Kr::Cv(v), Kr::C(a)) => v.push(a),
+            (Kr::Sv(v), Kr::S(a)) => v.push(a),
+            (Kr::Enumv(d, v), Kr::Enum(d2, a)) if d.name() == d2.name() => { *d = d.latest(&d2).unwrap_or(d2); v.push(a) },
+            (Kr::NN(v), a) => v.push(a),
+            (x, a) => {
+                let mut items = x.items();
+                items.push(a);
+                *x = Kr::NN(items);
+            },
+        }
+    }
+
+    // Join y onto the end of a list in place, as x,y
+    pub fn append(&mut self, y: &Kr) {
+        self.drop_attr();
+        if self.count().is_none() { *self = list::atom_to_vec(self) };
+        if self.disagrees(y) {
+            *self = self.symbols().unwrap();
+            return self.append(&y.symbols().unwrap());
+        }
+        match (&mut *self, y) {
+            (Kr::Bv(v), Kr::Bv(w)) => v.extend_from_slice(w),
+            (Kr::Iv(v), Kr::Iv(w)) => v.extend_from_slice(w),
+            (Kr::Jv(v), Kr::Jv(w)) => v.extend_from_slice(w),
+            (Kr::Ev(v), Kr::Ev(w)) => v.extend_from_slice(w),
+            (Kr::Fv(v), Kr::Fv(w)) => v.extend_from_slice(w),
+            (Kr::Cv(v), Kr::Cv(w)) => v.extend_from_slice(w),
+            (Kr::Sv(v), Kr::Sv(w)) => v.extend_from_slice(w),
+            (Kr::Enumv(d, v), Kr::Enumv(d2, w)) if d.name() == d2.name() => { *d = d.latest(d2).unwrap_or(d2.clone()); v.extend_from_slice(w) },
+            (Kr::NN(v), Kr::NN(w)) => v.extend_from_slice(w),
+            (_, y) => for item in y.items() { self.push_item(item) },
+        }
+    }
+
+    // A list without its attribute
+    pub fn plain(&self) -> &Kr {
+        match self {
+            Kr::Attr(_, x) => x,
+            x => x,
+        }
+    }
+
+    // Changing a list in place loses its attribute
+    fn drop_attr(&mut self) {
+        if let Kr::Attr(_, x) = self { *self = std::mem::replace(&mut **x, Kr::Null) };
+    }
+
+    // Enumerations over snapshots of one domain that don't agree, whose indices can't be mixed.
+    // These are joined as the symbols they stand for
+    pub fn disagrees(&self, y: &Kr) -> bool {
+        match (self.plain(), y.plain()) {
+            (Kr::Enum(d, _) | Kr::Enumv(d, _), Kr::Enum(d2, _) | Kr::Enumv(d2, _)) => d.name() == d2.name() && d.latest(d2).is_none(),
+            _ => false,
+        }
+    }
+
+    // Operators, primitives and lambdas
+    pub fn is_function(&self) -> bool {
+        matches!(self, Kr::Op(_) | Kr::Prim(_) | Kr::Lambda(_))
+    }
+
+    // An enumeration as the symbols it stands for
+    pub fn symbols(&self) -> Option<Kr> {
+        match self {
+            Kr::Enum(d, i) => Some(Kr::S(d.sym(*i))),
+            Kr::Enumv(d, v) => Some(Kr::Sv(v.iter().map(|i| d.sym(*i)).collect())),
+            Kr::Attr(_, x) => x.symbols(),
+            _ => None,
+        }
+    }
+
+    // Whether an atom counts as true in a condition: booleans, or any non-zero number
+    pub fn is_true(&self) -> Result<bool, KrEvalError> {
+        match self {
+            Kr::B(b) => Ok(*b),
+            Kr::I(n) => Ok(*n != 0),
+            Kr::J(n) => Ok(*n != 0),
+            Kr::E(n) => Ok(*n != 0.0),
+            Kr::F(n) => Ok(*n != 0.0),
+            Kr::C(c) => Ok(*c != 0),
+            other => Err(KrEvalError::type_of(&[other])),
+        }
+    }
+
+    // Index of the first item matching x
+    // A list with an attribute searches through it instead
+    pub fn find(&self, x: &Kr) -> Option<usize> {
+        if let Kr::Attr(a, list) = self { return a.find(list, x) };
+        (0..self.count().unwrap_or(0)).position(|i| self.item(i).as_ref() == Some(x))
+    }
+
+    // All items of a list, atoms are treated as a list of one
+    pub fn items(&self) -> Vec<Kr> {
+        match self.count() {
+            Some(n) => (0..n).filter_map(|i| self.item(i)).collect(),
+            None => vec![self.clone()],
+        }
+    }
+
+    // Build a list from items, using a typed vector when every item is an atom of the same type
+    pub fn from_items(items: Vec<Kr>) -> Kr {
+        macro_rules! collapse {
+            ($atom:ident, $vector:ident) => {
+                if items.iter().all(|x| matches!(x, Kr::$atom(_))) {
+                    return Kr::$vector(items.iter().map(|x| match x { Kr::$atom(a) => *a, _ => unreachable!() }).collect());
+                }
+            };
+        }
+        if items.is_empty() { return Kr::NN(items) };
+        collapse!(B, Bv);
+        collapse!(I, Iv);
+        collapse!(J, Jv);
+        collapse!(E, Ev);
+        collapse!(F, Fv);
+        collapse!(C, Cv);
+        if items.iter().all(|x| matches!(x, Kr::S(_))) {
+            return Kr::Sv(items.into_iter().map(|x| match x { Kr::S(a) => a, _ => unreachable!() }).collect());
+        }
+        // Enumerated atoms of one domain stay enumerated
+        if let Some(Kr::Enum(d, _)) = items.first() {
+            let d = d.clone();
+            if items.iter().all(|x| matches!(x, Kr::Enum(d2, _) if d2.name() == d.name())) {
+                let domain = items.iter().try_fold(d, |d, x| match x {
+                    Kr::Enum(d2, _) => d.latest(d2),
+                    _ => unreachable!(),
+                });
+                return match domain {
+                    Some(d) => Kr::Enumv(d, items.iter().map(|x| match x { Kr::Enum(_, i) => *i, _ => unreachable!() }).collect()),
+                    None => Kr::Sv(items.iter().map(|x| match x { Kr::Enum(d, i) => d.sym(*i), _ => unreachable!() }).collect()),
+                };
+            }
+        }
+        Kr::NN(items)
+    }
+}
+
+fn vec_to_string<T>(v: &[T], delim: &str, start: &str, end: &str) -> String 
 where T:ToString
 {
     let x: Vec<String> = v.iter().map(|x| x.to_string()).collect();
     format!("{}{}{}", start, x.join(delim), end)
-}
\ No newline at end of file
+}
+
+// Characters as they would be written in a string literal
+// Valid UTF-8 is kept as it is, other bytes and control characters are written in octal
+fn escape(bytes: &[u8]) -> String {
+    let mut output = String::new();
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '"' => output.push_str("\\\""),
+                '\\' => output.push_str("\\\\"),
+                '\n' => output.push_str("\\n"),
+                '\t' => output.push_str("\\t"),
+                '\r' => output.push_str("\\r"),
+                c if c.is_control() => {
+                    let mut buf = [0u8; 4];
+                    for b in c.encode_utf8(&mut buf).bytes() { output.push_str(&format!("\\{:03o}", b)) };
+                },
+                c => output.push(c),
+            }
+        }
+        for b in chunk.invalid() { output.push_str(&format!("\\{:03o}", b)) };
+    }
+    output
+}
+
+fn print_int(n: i32) -> String {
+    if n == NULL_I { "0N".to_string() } else { n.to_string() }
+}
+
+fn print_long(n: i64) -> String {
+    if n == NULL_J { "0N".to_string() } else { n.to_string() }
+}
+
+// Significant digits shown for floats, set by \P, 0 shows them all
+// Held here rather than in Env since printing does not see the environment
+static PRECISION: AtomicUsize = AtomicUsize::new(0);
+
+pub fn precision() -> usize {
+    PRECISION.load(Ordering::Relaxed)
+}
+
+pub fn set_precision(digits: usize) {
+    PRECISION.store(digits, Ordering::Relaxed);
+}
+
+fn print_float(n: f64) -> String {
+    if n.is_nan() { return "0n".to_string() };
+    if n.is_infinite() { return if n > 0.0 { "0w" } else { "-0w" }.to_string() };
+    let digits = precision();
+    if digits == 0 || n == 0.0 { return n.to_string() };
+    // Decimals left after the digits before the point, trailing zeros dropped
+    let exponent = n.abs().log10().floor() as i64;
+    let decimals = (digits as i64 - 1 - exponent).max(0) as usize;
+    let text = format!("{:.*}", decimals, n);
+    if !text.contains('.') { return text };
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+// Two values are equal if they have the same type and items, nulls are equal to each other
+impl PartialEq for Kr {
+    fn eq(&self, other: &Kr) -> bool {
+        fn float_eq(a: f64, b: f64) -> bool { a == b || (a.is_nan() && b.is_nan()) }
+        // Attributes do not change the value
+        if let Kr::Attr(_, x) = self { return **x == *other };
+        if let Kr::Attr(_, y) = other { return *self == **y };
+        // Enumerations compare as their symbols
+        if let Some(x) = self.symbols() { return &x == other };
+        if let Some(y) = other.symbols() { return self == &y };
+        match (self, other) {
+            (Kr::B(a), Kr::B(b)) => a == b,
+            (Kr::I(a), Kr::I(b)) => a == b,
+            (Kr::J(a), Kr::J(b)) => a == b,
+            (Kr::E(a), Kr::E(b)) => float_eq(*a as f64, *b as f64),
+            (Kr::F(a), Kr::F(b)) => float_eq(*a, *b),
+            (Kr::C(a), Kr::C(b)) => a == b,
+            (Kr::S(a), Kr::S(b)) => a == b,
+            (Kr::Bv(a), Kr::Bv(b)) => a == b,
+            (Kr::Iv(a), Kr::Iv(b)) => a == b,
+            (Kr::Jv(a), Kr::Jv(b)) => a == b,
+            (Kr::Ev(a), Kr::Ev(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| float_eq(*a as f64, *b as f64)),
+            (Kr::Fv(a), Kr::Fv(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| float_eq(*a, *b)),
+            (Kr::Cv(a), Kr::Cv(b)) => a == b,
+            (Kr::Sv(a), Kr::Sv(b)) => a == b,
+            (Kr::NN(a), Kr::NN(b)) => a == b,
+            (Kr::Dict(ak, av), Kr::Dict(bk, bv)) => ak == bk && av == bv,
+            (Kr::Op(a), Kr::Op(b)) => a.to_string() == b.to_string(),
+            (Kr::Prim(a), Kr::Prim(b)) => a.to_string() == b.to_string(),
+            (Kr::Lambda(a), Kr::Lambda(b)) => a.to_string() == b.to_string(),
+            (Kr::Null, Kr::Null) => true,
+            (_, _) => false,
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+
+use crate::error::KrEvalError;
+use crate::eval::eval;
+use crate::init::Env;
+use crate::kr::Kr;
+use crate::text::Text;
+
+/*
+A user defined function: {[a;b] a+b}, or {x+y} with implicit parameters x y z
+Each call gets its own frame of local variables in the Env
+A lambda created inside another function captures the values of the
+enclosing locals it refers to, so it still sees them after that function returns
+*/
+#[derive(Clone, Debug)]
+pub struct Lambda {
+    params: Vec<Text>,
+    body: Vec<Kr>,
+    names: Vec<Text>,           // Names used in the body, candidates for capture
+    closure: Vec<(Text, Kr)>,   // Captured values of enclosing locals
+    text: Text,                 // Source, spans in the body are offsets from the start of the input it came from
+    offset: usize,              // Offset of the { in that input
+    name: Option<Text>,         // Name it was first assigned to
+    file: Option<(Text, usize)>,    // Script and line of the { for a lambda loaded from a file
+}
+
+impl Lambda {
+    pub fn new(params: Vec<Text>, body: Vec<Kr>, names: Vec<Text>, text: Text, offset: usize) -> Self {
+        let names = names.into_iter().filter(|n| !params.contains(n)).collect();
+        Lambda { params, body, names, closure: Vec::new(), text, offset, name: None, file: None }
+    }
+
+    // Offset of the { in the input it came from
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    // The same lambda written at a line of a script, with f applied to its body to place lambdas within it
+    pub fn in_file(&self, path: &Text, line: usize, f: impl Fn(&Kr) -> Kr) -> Self {
+        let body = self.body.iter().map(f).collect();
+        Lambda { body, file: Some((path.clone(), line)), ..self.clone() }
+    }
+
+    pub fn name(&self) -> Option<&Text> {
+        self.name.as_ref()
+    }
+
+    // The same lambda, named if it has no name yet
+    pub fn named(&self, name: &Text) -> Self {
+        Lambda { name: self.name.clone().or_else(|| Some(name.clone())), ..self.clone() }
+    }
+
+    // Copy of this lambda holding the current values of the enclosing locals it uses
+    pub fn capture(&self, env: &Env) -> Self {
+        let mut closure = self.closure.clone();
+        for name in &self.names {
+            if let Some(value) = env.local(name) {
+                closure.push((name.clone(), value.clone()));
+            }
+        }
+        Lambda { closure, ..self.clone() }
+    }
+
+    pub fn apply(&self, env: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+        if args.len() != self.params.len() { return (env, Err(KrEvalError::Rank)) };
+        let mut frame: HashMap<Text, Kr> = self.closure.iter().cloned().collect();
+        for (name, value) in self.params.iter().zip(args) {
+            frame.insert(name.clone(), value.clone());
+        }
+        let mut e = env;
+        e.push_frame(frame);
+        let mut result = Ok(Kr::Null);
+        for statement in &self.body {
+            let (new_env, res) = eval(e, statement);
+            e = new_env;
+            result = res;
+            if result.is_err() { break };
+        }
+        // The frame is dropped on every path, including errors
+        e.pop_frame();
+        // An error site within the body is relative to this lambda's source
+        if result.is_err() {
+            e.locate_error(|site| site.within(&self.text, self.offset, self.name.clone(), self.file.clone()));
+        }
+        (e, result)
+    }
+}
+
+impl std::fmt::Display for Lambda {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
@@ -1,23 +1,70 @@
+use crate::error::KrLexError;
 use crate::text::Text;
-use crate::token::{Token, KrToken, NameToken, NumberToken, OperatorToken, QuotedToken, SymbolToken};
+use crate::token::{Token, KrToken, Lexed, NameToken, NumberToken, OperatorToken, QuotedToken, Span, SymbolToken};
 
 
 fn read_number(input: &[u8]) -> usize {
-    let mut i = 0;
-    for &c in input.iter() {
-        let end = match c {
-            b'0'..=b'9' => { i = i + 1; false },
-            b'e' | b'f' | b'i' | b'j' => { i = i + 1; true },
-            _ => true,
-        };
-        if end { break; }
+    let mut point = false;
+    for (i, &c) in input.iter().enumerate() {
+        match c {
+            b'0'..=b'9' => continue,
+            // Decimal point, only one and only when a digit follows: 1.5
+            b'.' if !point && input.get(i+1).is_some_and(|x| x.is_ascii_digit()) => { point = true; continue },
+            // Nulls and infinity: 0N 0n 0w
+            b'N' | b'n' | b'w' if i == 1 && input[0] == b'0' => continue,
+            b'b' | b'e' | b'f' | b'i' | b'j' => return i + 1,
+            _ => return i,
+        }
+    }
+    input.len()
+}
+
+// A number must not run into a letter or digit, 1x and 1.2.3 are errors
+// while _ . and operators end it, so 2_x drops and 1.x is 1 then .x
+// Boolean vectors may only hold 0 and 1
+fn check_number(input: &[u8], j: usize) -> Result<(), usize> {
+    let rest = &input[j..];
+    let dotted = rest.first() == Some(&b'.') && rest.get(1).is_some_and(|c| c.is_ascii_digit());
+    let skip = if dotted { 1 } else { 0 };
+    let run = rest[skip..].iter().take_while(|c| c.is_ascii_alphanumeric()).count();
+    let end = j + if run > 0 { skip + run } else { 0 };
+    let bad_bits = input[j-1] == b'b' && input[..j-1].iter().any(|c| !matches!(c, b'0' | b'1' | b'-'));
+    if end > j || bad_bits { Err(end) } else { Ok(()) }
+}
+
+// Index of the closing quote of a string starting at i, skipping escaped characters
+fn end_of_string(input: &[u8], i: usize) -> Option<usize> {
+    let mut k = i;
+    while k < input.len() {
+        match input[k] {
+            b'"' => return Some(k),
+            b'\\' => k += 2,
+            _ => k += 1,
+        }
     }
-    i
+    None
 }
 
-pub fn lex(input: &String) -> Vec<Token> {
+// Letters, digits, underscores and dots may follow the first character of a name
+fn is_name_char(c: &u8) -> bool {
+    c.is_ascii_alphanumeric() || *c == b'_' || *c == b'.'
+}
+
+// True if the previous token ends a noun, so a following - is subtraction not a sign
+fn after_noun(tokens: &[Token]) -> bool {
+    matches!(
+        tokens.last(),
+        Some(Token::KrToken(KrToken::Name(_))) | Some(Token::KrToken(KrToken::Number(_)))
+        | Some(Token::KrToken(KrToken::Quoted(_))) | Some(Token::KrToken(KrToken::Symbol(_)))
+        | Some(Token::RParen) | Some(Token::RBracket) | Some(Token::RBrace)
+    )
+}
+
+// Tokens of the input, or the first error and where it is
+pub fn lex(input: &str) -> Result<Lexed, (KrLexError, Span)> {
     // input.split_whitespace().collect()
     let mut tokens: Vec<Token> = Vec::new();
+    let mut spans: Vec<Span> = Vec::new();
     let mut i = 0; // Index
     // let input = input.as_bytes().to_owned();
     let input = Text::from_str(input);
@@ -28,29 +75,45 @@ pub fn lex(input: &String) -> Vec<Token> {
         let j: usize;
         let tok: Token;
         match c {
-            b'a'..=b'z' | b'A'..=b'Z' => {
-                // Name - must look ahead
-                j = input.find_first(|x: &u8| !x.is_ascii_alphabetic(), i);
+            b'a'..=b'z' | b'A'..=b'Z' | b'.' if c.is_ascii_alphabetic() || input.get(i+1).is_some_and(|x| x.is_ascii_alphabetic()) => {
+                // Name - must look ahead, .ns.name is a name in a namespace
+                j = input.find_first(|x: &u8| !is_name_char(x), i+1);
                 tok = Token::KrToken(KrToken::Name(NameToken::new(Text::from_slice(&input.0[i..j]))));
             },
-            b'0'..=b'9' => {
+            b'0'..=b'9' | b'.' if c.is_ascii_digit() || input.get(i+1).is_some_and(|x| x.is_ascii_digit()) => {
                 // Number - must look ahead
                 j = i + read_number(&input.0[i..]);
+                check_number(&input.0[i..], j - i).map_err(|end| (KrLexError::InvalidNumber, Span { start: i, end: i + end }))?;
                 tok = Token::KrToken(KrToken::Number(NumberToken::new(Text::from_slice(&input.0[i..j]))));
             },
-            b'+' | b'-' | b'*' | b'%' | b':' | b',' => {
-                // Operator - push now
-                j = i + 1;
-                tok = Token::KrToken(KrToken::Operator(OperatorToken::new(Text::from_slice(&input.0[i..j]))));
+            b'-' if !after_noun(&tokens) && input.get(i+1).is_some_and(|x| x.is_ascii_digit()) => {
+                // Negative number
+                j = i + 1 + read_number(&input.0[i+1..]);
+                check_number(&input.0[i..], j - i).map_err(|end| (KrLexError::InvalidNumber, Span { start: i, end: i + end }))?;
+                tok = Token::KrToken(KrToken::Number(NumberToken::new(Text::from_slice(&input.0[i..j]))));
+            },
+            b'+' | b'-' | b'*' | b'%' | b':' | b',' | b'#' | b'_' | b'$' | b'!' | b'@' | b'.' | b'=' | b'<' | b'>' | b'?' => {
+                // Operator - push now, along with a following : for compound assignment x+:y
+                // :: is global assignment
+                j = if input.get(i+1) == Some(&b':') { i + 2 } else { i + 1 };
+                let op = OperatorToken::new(Text::from_slice(&input.0[i..j])).map_err(|err| (err, Span { start: i, end: j }))?;
+                tok = Token::KrToken(KrToken::Operator(op));
             },
             b'"' => {
-                j = 1 + input.find_first(|x: &u8| *x == b'"', i+1);
-                tok = Token::KrToken(KrToken::Quoted(QuotedToken::new(Text::from_slice(&input.0[i+1..j-1])))); 
+                j = 1 + end_of_string(&input.0, i+1).ok_or((KrLexError::UnterminatedString, Span { start: i, end: input.len() }))?;
+                let quoted = QuotedToken::new(Text::from_slice(&input.0[i+1..j-1])).map_err(|err| (err, Span { start: i, end: j }))?;
+                tok = Token::KrToken(KrToken::Quoted(quoted));
             },
             b'`' => {
-                j = input.find_first(|x: &u8| !x.is_ascii_alphabetic(), i+1);
+                // Symbols written together form a vector: `a`b`c
+                j = input.find_first(|x: &u8| !is_name_char(x) && *x != b'`', i+1);
                 tok = Token::KrToken(KrToken::Symbol(SymbolToken::new(Text::from_slice(&input.0[i+1..j])))); 
             },
+            b'\'' => {
+                // Signal an error: '"msg", a token of its own so it can't be assigned to
+                j = i + 1;
+                tok = Token::KrToken(KrToken::Signal);
+            },
             b'(' => {
                 j = i + 1;
                 tok = Token::LParen;
@@ -60,26 +123,65 @@ pub fn lex(input: &String) -> Vec<Token> {
                 tok = Token::RParen;
             },
             b'[' => {
+                // Directly following a term (no whitespace) it applies that term: f[x;y]
                 j = i + 1;
-                tok = Token::LBracket;
+                let adjacent = i > 0 && !input.0[i-1].is_ascii_whitespace();
+                tok = match tokens.last() {
+                    Some(Token::KrToken(_)) | Some(Token::RParen) | Some(Token::RBracket) | Some(Token::RBrace) if adjacent => Token::LApply,
+                    _ => Token::LBracket,
+                };
             },
             b']' => {
                 j = i + 1;
                 tok = Token::RBracket;
             },
+            b'{' => {
+                j = i + 1;
+                tok = Token::LBrace;
+            },
+            b'}' => {
+                j = i + 1;
+                tok = Token::RBrace;
+            },
             b';' => {
                 j = i + 1;
                 tok = Token::SemiColon;
             },
-            _ => {
-                // Other - ignore
-                j = i + 1;
-                i = j;
+            _ if c.is_ascii_whitespace() => {
+                i += 1;
                 continue;
             },
+            _ => {
+                // Cover every byte of a multi-byte UTF-8 character
+                let n = 1 + input.0[i+1..].iter().take_while(|b| (**b & 0xc0) == 0x80).count();
+                return Err((KrLexError::UnknownCharacter, Span { start: i, end: i + n }));
+            },
         };
         tokens.push(tok);
+        spans.push(Span { start: i, end: j });
         i = j;
     }
-    tokens
-}
\ No newline at end of file
+    Ok(Lexed { tokens, spans, source: input })
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // _ after a number is drop or cut, not part of the number
+    #[test]
+    fn number_then_underscore() {
+        assert!(lex("2_til 5").is_ok());
+        assert!(lex("1_x").is_ok());
+        assert!(lex("1x").is_err());
+        assert!(lex("1.2.3").is_err());
+    }
+
+    // ' signals, it is never a name that could be assigned
+    #[test]
+    fn signal_is_its_own_token() {
+        let Ok(tokens) = lex("':5") else { panic!("' should lex") };
+        assert!(matches!(tokens.get(0), Some(Token::KrToken(KrToken::Signal))));
+        assert_eq!(crate::eval::run(&["':5"]), None);
+        assert_eq!(crate::eval::run(&["@[{'\"x\"};0;{x}]"]), crate::eval::run(&["\"x\""]));
+    }
+}
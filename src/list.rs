@@ -0,0 +1,209 @@
+use crate::error::KrEvalError;
+use crate::kr::Kr;
+
+/*
+Structural functions shared by the list verbs
+Each one works on any typed vector or general list
+*/
+
+// Apply a generic expression to the vector inside any list variant
+// keeping the variant of the result the same as the input
+macro_rules! vector_map {
+    ($x:expr, $v:ident => $body:expr) => {
+        match $x {
+            Kr::Bv($v) => Ok(Kr::Bv($body)),
+            Kr::Iv($v) => Ok(Kr::Iv($body)),
+            Kr::Jv($v) => Ok(Kr::Jv($body)),
+            Kr::Ev($v) => Ok(Kr::Ev($body)),
+            Kr::Fv($v) => Ok(Kr::Fv($body)),
+            Kr::Cv($v) => Ok(Kr::Cv($body)),
+            Kr::Sv($v) => Ok(Kr::Sv($body)),
+            Kr::Enumv(d, $v) => Ok(Kr::Enumv(d.clone(), $body)),
+            Kr::NN($v) => Ok(Kr::NN($body)),
+            other => Err(KrEvalError::Type(vec![other.type_code()])),
+        }
+    };
+}
+
+pub fn atom_to_vec(x: &Kr) -> Kr {
+    match x {
+        Kr::B(a) => Kr::Bv([*a].to_vec()),
+        Kr::I(a) => Kr::Iv([*a].to_vec()),
+        Kr::J(a) => Kr::Jv([*a].to_vec()),
+        Kr::E(a) => Kr::Ev([*a].to_vec()),
+        Kr::F(a) => Kr::Fv([*a].to_vec()),
+        Kr::C(a) => Kr::Cv([*a].to_vec()),
+        Kr::S(a) => Kr::Sv([*a].to_vec()),
+        Kr::Enum(d, a) => Kr::Enumv(d.clone(), [*a].to_vec()),
+        Kr::Op(_) | Kr::Prim(_) | Kr::Lambda(_) | Kr::Null | Kr::Dict(_, _) => Kr::NN(vec![x.clone()]),
+        other => other.clone(),
+    }
+}
+
+// An integer atom as an i64
+pub fn as_long(x: &Kr) -> Option<i64> {
+    match x {
+        Kr::B(n) => Some(*n as i64),
+        Kr::I(n) => Some(*n as i64),
+        Kr::J(n) => Some(*n),
+        _ => None,
+    }
+}
+
+// An integer vector (or atom) as a Vec<i64>
+pub fn as_longs(x: &Kr) -> Option<Vec<i64>> {
+    match x {
+        Kr::Bv(v) => Some(v.iter().map(|n| *n as i64).collect()),
+        Kr::Iv(v) => Some(v.iter().map(|n| *n as i64).collect()),
+        Kr::Jv(v) => Some(v.clone()),
+        other => as_long(other).map(|n| vec![n]),
+    }
+}
+
+// An empty vector with room for n items, an error rather than an abort if there is no memory for them
+pub fn alloc<T>(n: u64) -> Result<Vec<T>, KrEvalError> {
+    let mut v = Vec::new();
+    let n = usize::try_from(n).map_err(|_| KrEvalError::Memory)?;
+    v.try_reserve_exact(n).map_err(|_| KrEvalError::Memory)?;
+    Ok(v)
+}
+
+// First n items, wrapping around if n is larger than the list
+// Negative n takes from the end
+fn take_vec<T: Clone>(v: &[T], n: i64) -> Result<Vec<T>, KrEvalError> {
+    if n == 0 { return Ok(Vec::new()) };
+    if v.is_empty() { return Err(KrEvalError::Length) };
+    let len = v.len() as u64;
+    let count = n.unsigned_abs();
+    let start = if n > 0 { 0 } else { (len - count % len) % len };
+    let mut out = alloc(count)?;
+    out.extend(v.iter().cycle().skip(start as usize).take(count as usize).cloned());
+    Ok(out)
+}
+
+// All but the first n items, or all but the last -n items
+fn drop_vec<T: Clone>(v: &[T], n: i64) -> Vec<T> {
+    let count = (n.unsigned_abs() as usize).min(v.len());
+    if n >= 0 {
+        v[count..].to_vec()
+    } else {
+        v[..v.len() - count].to_vec()
+    }
+}
+
+// Rotate left by n, negative n rotates right
+fn rotate_vec<T: Clone>(v: &[T], n: i64) -> Vec<T> {
+    if v.is_empty() { return Vec::new() };
+    let mut out = v.to_vec();
+    out.rotate_left(n.rem_euclid(v.len() as i64) as usize);
+    out
+}
+
+pub fn take(n: i64, x: &Kr) -> Result<Kr, KrEvalError> {
+    vector_map!(atom_to_vec(x), v => take_vec(&v, n)?)
+}
+
+pub fn drop(n: i64, x: &Kr) -> Result<Kr, KrEvalError> {
+    vector_map!(x, v => drop_vec(v, n))
+}
+
+pub fn reverse(x: &Kr) -> Result<Kr, KrEvalError> {
+    match x.count() {
+        None => Ok(x.clone()),
+        Some(_) => vector_map!(x, v => v.iter().rev().cloned().collect()),
+    }
+}
+
+pub fn rotate(n: i64, x: &Kr) -> Result<Kr, KrEvalError> {
+    match x.count() {
+        None => Ok(x.clone()),
+        Some(_) => vector_map!(x, v => rotate_vec(v, n)),
+    }
+}
+
+// Reshape x into nested lists with the given shape, cycling through the items of x
+pub fn reshape(shape: &[i64], x: &Kr) -> Result<Kr, KrEvalError> {
+    if shape.iter().any(|n| *n < 0) { return Err(KrEvalError::Domain) };
+    let size = shape.iter().try_fold(1i64, |size, n| size.checked_mul(*n)).ok_or(KrEvalError::Memory)?;
+    let flat = take(size, x)?;
+    Ok(reshape_flat(shape, &flat, 0).0)
+}
+
+// Build one level of the reshape from the flat list, starting at item i
+// Returns the result and the index of the next unused item
+fn reshape_flat(shape: &[i64], flat: &Kr, i: usize) -> (Kr, usize) {
+    match shape {
+        [] => (flat.item(i).unwrap_or(Kr::Null), i + 1),
+        [n] => {
+            let n = *n as usize;
+            let slice = drop(i as i64, flat).and_then(|rest| take(n as i64, &rest)).unwrap_or(Kr::NN(Vec::new()));
+            (slice, i + n)
+        },
+        [n, rest @ ..] => {
+            let mut rows: Vec<Kr> = Vec::new();
+            let mut j = i;
+            for _ in 0..*n {
+                let (row, k) = reshape_flat(rest, flat, j);
+                rows.push(row);
+                j = k;
+            }
+            (Kr::NN(rows), j)
+        },
+    }
+}
+
+// Cut x into pieces starting at each of the (ascending) indices
+pub fn cut(indices: &[i64], x: &Kr) -> Result<Kr, KrEvalError> {
+    let len = x.count().ok_or(KrEvalError::type_of(&[x]))? as i64;
+    if indices.windows(2).any(|w| w[0] > w[1]) { return Err(KrEvalError::Domain) };
+    if indices.iter().any(|i| *i < 0 || *i > len) { return Err(KrEvalError::Length) };
+    let mut pieces: Vec<Kr> = Vec::new();
+    for (k, start) in indices.iter().enumerate() {
+        let end = indices.get(k + 1).copied().unwrap_or(len);
+        pieces.push(take(end - start, &drop(*start, x)?)?);
+    }
+    Ok(Kr::NN(pieces))
+}
+
+// Join the items of a list together, removing one level of nesting
+pub fn raze(x: &Kr) -> Result<Kr, KrEvalError> {
+    match x {
+        Kr::NN(list) => Ok(Kr::from_items(list.iter().flat_map(|k| k.items()).collect())),
+        other => Ok(other.clone()),
+    }
+}
+
+// Transpose a rectangular list of lists, atoms are repeated down the column
+pub fn flip(x: &Kr) -> Result<Kr, KrEvalError> {
+    let Kr::NN(rows) = x else { return Err(KrEvalError::type_of(&[x])) };
+    let counts: Vec<usize> = rows.iter().filter_map(|r| r.count()).collect();
+    let n = match counts.first() {
+        Some(n) => *n,
+        None => return Err(KrEvalError::type_of(&[x])),
+    };
+    if counts.iter().any(|c| *c != n) { return Err(KrEvalError::Length) };
+    let columns = (0..n)
+        .map(|i| Kr::from_items(rows.iter().map(|r| r.item(i).unwrap_or_else(|| r.clone())).collect()))
+        .collect();
+    Ok(Kr::NN(columns))
+}
+
+// Apply f to each atom of x at any depth
+pub fn atomic1(x: &Kr, f: &dyn Fn(&Kr) -> Result<Kr, KrEvalError>) -> Result<Kr, KrEvalError> {
+    match x.count() {
+        None => f(x),
+        Some(_) => Ok(Kr::from_items(x.items().iter().map(|a| atomic1(a, f)).collect::<Result<Vec<Kr>, KrEvalError>>()?)),
+    }
+}
+
+// Apply f between atoms, extending over lists item by item
+pub fn atomic2(x: &Kr, y: &Kr, f: &dyn Fn(&Kr, &Kr) -> Result<Kr, KrEvalError>) -> Result<Kr, KrEvalError> {
+    let items: Result<Vec<Kr>, KrEvalError> = match (x.count(), y.count()) {
+        (None, None) => return f(x, y),
+        (Some(n), Some(m)) if n != m => return Err(KrEvalError::Length),
+        (Some(_), Some(_)) => x.items().iter().zip(y.items().iter()).map(|(a, b)| atomic2(a, b, f)).collect(),
+        (Some(_), None) => x.items().iter().map(|a| atomic2(a, y, f)).collect(),
+        (None, Some(_)) => y.items().iter().map(|b| atomic2(x, b, f)).collect(),
+    };
+    Ok(Kr::from_items(items?))
+}
@@ -1,106 +1,246 @@
-use std::io::{self, Write};
+use std::io::{self, BufRead, IsTerminal};
 mod operator;
 
 mod kr;
 use error::KrEvalError;
 use kr::Kr;
 
+mod eval;
+use eval::eval;
+
 mod init;
 use crate::error::KrError;
 use crate::init::Env;
 
 mod text;
 mod token;
-use crate::token::Token;
+use crate::token::{Lexed, Token};
+use crate::text::Text;
 mod lex;
 mod parse;
 mod error;
 mod primitive;
+mod list;
+mod cast;
+mod index;
+mod lambda;
+mod system;
+mod strings;
+mod sym;
+mod enums;
+mod attr;
+mod random;
+mod math;
+mod script;
+mod editor;
+mod heap;
+use crate::editor::Editor;
 
 
-fn read() -> String {
-    print!("kr>");
-    io::stdout().flush().expect("Failed to flush stdout");
-    let mut input = String::new();
-    let _res = io::stdin().read_line(&mut input).expect("Failed to read line");
-    input
+// A line of piped input, None at the end of input
+// Bytes that aren't UTF-8 are read as the replacement character
+fn read() -> Option<String> {
+    let mut input = Vec::new();
+    match io::stdin().lock().read_until(b'\n', &mut input) {
+        Ok(0) => None,
+        Ok(_) => Some(String::from_utf8_lossy(&input).into_owned()),
+        Err(e) => {
+            eprintln!("'{}", e);
+            None
+        },
+    }
 }
 
-fn eval(env: Env, ast: &Kr) -> (Env, Result<Kr, KrEvalError>) {
-    // Recursively evaluate ast
-    // A list of the form [(::); `name] means the value assigned to that name
-    match ast {
-        Kr::NN(t) => {
-            match t.len() {
-                0 => (env, Ok(Kr::Null)),
-                1 => (env, Ok(t[0].clone())),
-                _ => {
-                    // Initialize a vector to store the results
-                    let mut results: Vec<Kr> = Vec::new();
-                    let mut e: Env = env;
-                    // Iterate through the elements of t, starting from the second element (index 1)
-                    for i in 0..t.len() {
-                        let (new_env, kr) = eval(e, &t[i]);
-                        let kr = match kr {
-                            Ok(x) => x,
-                            Err(err) => return (new_env, Err(err)),
-                        };
-                        // Append the result to the results vector
-                        results.push(kr);
-                        // Update the environment for the next iteration
-                        e = new_env;
-                    }
-                    let (first, rest) = results.split_first().expect("results should not be empty");
-                    first.apply(e, rest)                   
-                }
-            }
+// Print a value cut to the console size set by \c, .. marks what is left out
+fn print(output: &Kr, (rows, cols): (usize, usize)) {
+    let text = output.print();
+    let lines: Vec<&str> = text.split('\n').collect();
+    let shown = if lines.len() < rows { lines.len() } else { rows - 2 };
+    for line in &lines[..shown] {
+        match line.char_indices().nth(cols - 2) {
+            Some((end, _)) if line.chars().count() > cols => println!("{}..", &line[..end]),
+            _ => println!("{}", line),
+        }
+    }
+    if shown < lines.len() { println!("..") };
+}
+
+// Command line: kr-a [file] [-e expr] [-q] [--exit] [--debug] [--no-interrupt]
+struct Options {
+    script: Option<String>,     // Loaded before anything else
+    expr: Option<String>,       // -e evaluates and prints one expression, then exits
+    quiet: bool,                // -q prints no prompt and does not echo values typed or piped, only errors
+    exit: bool,                 // --exit quits once the script has run
+    debug: bool,
+    interrupt: bool,            // --no-interrupt leaves Ctrl-C to kill the process rather than stop a running loop
+}
+
+fn options(args: &[String]) -> Options {
+    let mut opts = Options { script: None, expr: None, quiet: false, exit: false, debug: false, interrupt: true };
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-e" => opts.expr = args.next().cloned(),
+            "-q" => opts.quiet = true,
+            "--exit" => opts.exit = true,
+            "--debug" => opts.debug = true,
+            "--no-interrupt" => opts.interrupt = false,
+            _ if !arg.starts_with('-') && opts.script.is_none() => opts.script = Some(arg.clone()),
+            _ => {},
         }
-        other => (env, Ok(other.clone())),
     }
+    opts
 }
 
-fn print(output: &Kr) {
-    println!("{}", output.print());
+// Print an error at the line it happened on, then the calls that failed on the way out
+fn report(env: &mut Env, err: KrError, input: &Text) {
+    // The site is in this input unless a lambda or script has claimed it
+    let trace = env.take_trace();
+    match env.take_site() {
+        Some(site) => err.print_at(site.source.as_ref().unwrap_or(input), site.span, site.origin.as_ref()),
+        None => err.print(),
+    }
+    // A single call is already shown by the caret
+    if env.show_trace && trace.len() > 1 {
+        for (i, call) in trace.iter().enumerate() {
+            println!("  [{}] {}", i, call);
+        }
+    }
+}
+
+// What became of a line of input
+#[derive(PartialEq)]
+enum Outcome {
+    Done,
+    Failed,
+    Exit,       // \\ was run, main decides the exit code
+}
+
+// Report an error, unless it is a request to exit
+fn fail(env: &mut Env, err: KrError, input: &Text) -> Outcome {
+    if matches!(err, KrError::Eval(KrEvalError::Exit)) { return Outcome::Exit };
+    report(env, err, input);
+    Outcome::Failed
+}
+
+// Whether input stops inside a paren, bracket or brace, so the next line continues it
+fn incomplete(input: &str) -> bool {
+    if input.trim_start().starts_with('\\') { return false };
+    let Ok(tokens) = lex::lex(input) else { return false };
+    let depth: i64 = tokens.tokens.iter().map(|t| match t {
+        Token::LParen | Token::LBracket | Token::LApply | Token::LBrace => 1,
+        Token::RParen | Token::RBracket | Token::RBrace => -1,
+        _ => 0,
+    }).sum();
+    depth > 0 && parse::parse(&tokens).is_err()
+}
+
+// Evaluate one line of input and print its value if echo is set, or the error
+fn execute(env: Env, input: &str, opts: &Options, echo: bool) -> (Env, Outcome) {
+    let mut env = env;
+    if input.trim_start().starts_with('\\') {
+        let result: Result<Kr, KrError>;
+        (env, result) = system::command(env, input);
+        return match result {
+            Err(e) => { let outcome = fail(&mut env, e, &Text::from_str(input)); (env, outcome) },
+            Ok(Kr::Null) => (env, Outcome::Done),
+            Ok(res) => { if echo { print(&res, env.console) }; (env, Outcome::Done) },
+        };
+    }
+    let tokens: Lexed = match lex::lex(input) {
+        Err((e, span)) => {
+            KrError::Lex(e).print_at(&Text::from_str(input), span, None);
+            return (env, Outcome::Failed);
+        },
+        Ok(tokens) => tokens,
+    };
+    if opts.debug {
+        let token_strings: Vec<String> = tokens.tokens.iter().map(|x| x.as_string()).collect();
+        println!("{:?}", token_strings);
+    };
+    let ast = match parse::parse(&tokens) {
+        Err((e, span)) => {
+            KrError::Parse(e).print_at(&tokens.source, span, None);
+            return (env, Outcome::Failed);
+        },
+        Ok(ast) => ast,
+    };
+
+    if opts.debug { println!("{}",ast.print()); };
+
+    let result: Result<Kr, KrEvalError>;
+    (env, result) = eval(env, &ast);
+    match result {
+        Err(e) => {
+            let outcome = fail(&mut env, KrError::Eval(e), &tokens.source);
+            (env, outcome)
+        },
+        Ok(res) => {
+            if opts.debug { println!("type {}: {:?}", res.type_code(), res); };
+            // An empty line has no value to show
+            if echo && !tokens.is_empty() { print(&res, env.console) };
+            (env, Outcome::Done)
+        },
+    }
 }
 
 fn main() {
     // Startup logic here..
     let mut env: Env = init::init();
-    let debug = env.opts.iter().any(|s| s == "--debug");
-    
-    if debug { println!("Options {:?}", env.opts); };
+    let opts = options(&env.opts);
 
-    loop {
-        // REPL loop
-        let input = read();
-        let tokens: Vec<Token> = lex::lex(&input);
-        if debug { 
-            let token_strings: Vec<String> = tokens.iter().map(|x| x.as_string()).collect();
-            println!("{:?}", token_strings);
-        };
-        let ast = match parse::parse(&tokens) {
-            Err(e) => {
-                KrError::Parse(e).print();
-                continue;
-            },
-            Ok(ast) => ast,
-        };
+    if opts.debug { println!("Options {:?}", env.opts); };
+    if opts.interrupt { eval::install_interrupt(); };
 
-        if debug { println!("{}",ast.print()); };
-
-        let result: Result<Kr, KrEvalError>;
-        (env, result) = eval(env, &ast);
-        let result = match result {
-            Err(e) => {
-                KrError::Eval(e).print();
-                continue;
+    // kr-a file.k loads a script before the prompt, --exit leaves once it has run
+    let mut failed = false;
+    if let Some(path) = &opts.script {
+        let result: Result<Kr, KrError>;
+        (env, result) = script::load(env, path);
+        failed = match result {
+            Err(e) => match fail(&mut env, e, &Text::from_str("")) {
+                Outcome::Exit => std::process::exit(0),
+                _ => true,
             },
-            Ok(res) => res,
+            Ok(_) => false,
         };
-        if debug { println!("{:?}", result); };
+        if opts.exit || (failed && opts.expr.is_some()) { std::process::exit(failed as i32) };
+    }
 
-        print(&result);
+    if let Some(expr) = &opts.expr {
+        // -q only quiets the prompt, the value of -e is always printed
+        let (_, outcome) = execute(env, expr, &opts, true);
+        std::process::exit(if outcome == Outcome::Failed { 1 } else { 0 });
+    }
 
-        if input.len() == 0 { break; };
+    // A terminal gets the line editor, piped input is read without prompts
+    // and any error gives a failing exit code at the end
+    let interactive = io::stdin().is_terminal();
+    let mut editor = if interactive { Some(Editor::new()) } else { None };
+    let (prompt, more) = if opts.quiet { ("", "") } else { ("kr>", "  ") };
+    loop {
+        let line = match editor.as_mut() {
+            Some(editor) => editor.read_line(prompt),
+            None => read(),
+        };
+        let Some(mut input) = line else { break };
+        if let Some(editor) = editor.as_mut() {
+            while incomplete(&input) {
+                match editor.read_line(more) {
+                    Some(line) => input += &line,
+                    None => break,
+                }
+            }
+            editor.add(&input);
+        }
+        eval::clear_interrupt();
+        let outcome: Outcome;
+        (env, outcome) = execute(env, &input, &opts, !opts.quiet);
+        match outcome {
+            Outcome::Exit => break,
+            Outcome::Failed => failed = true,
+            Outcome::Done => {},
+        }
     }
+    if !interactive && failed { std::process::exit(1) };
 }
@@ -0,0 +1,190 @@
+use crate::cast::{self, Num};
+use crate::error::KrEvalError;
+use crate::kr::{Kr, NULL_I, NULL_J};
+use crate::list;
+
+/*
+Math functions, atomic over numbers at any depth
+    sqrt exp log sin cos tan asin acos atan reciprocal      always give floats
+    floor ceiling       round floats down or up to longs, integers are unchanged
+    abs neg             keep the type of ints, longs, reals and floats, booleans give longs
+    signum              -1, 0 or 1 as an int
+    xexp[x;y]           x to the power y, xlog[x;y] the log of y in base x, both floats
+    mod[x;y]            remainder with the sign of y, div[x;y] the quotient rounded down
+    wsum[w;x]           sum of w*x, wavg[w;x] the same divided by the sum of w, skipping nulls
+Nulls give nulls
+*/
+
+// Numeric value of a number, characters are not numbers here
+fn number(x: &Kr) -> Result<Num, KrEvalError> {
+    match x {
+        Kr::C(_) => Err(KrEvalError::type_of(&[x])),
+        _ => cast::num(x).ok_or(KrEvalError::type_of(&[x])),
+    }
+}
+
+fn float(x: &Kr, f: fn(f64) -> f64) -> Result<Kr, KrEvalError> {
+    list::atomic1(x, &|a| Ok(Kr::F(f(cast::real(&number(a)?)))))
+}
+
+pub fn sqrt(x: &Kr) -> Result<Kr, KrEvalError> { float(x, f64::sqrt) }
+pub fn exp(x: &Kr) -> Result<Kr, KrEvalError> { float(x, f64::exp) }
+pub fn log(x: &Kr) -> Result<Kr, KrEvalError> { float(x, f64::ln) }
+pub fn sin(x: &Kr) -> Result<Kr, KrEvalError> { float(x, f64::sin) }
+pub fn cos(x: &Kr) -> Result<Kr, KrEvalError> { float(x, f64::cos) }
+pub fn tan(x: &Kr) -> Result<Kr, KrEvalError> { float(x, f64::tan) }
+pub fn asin(x: &Kr) -> Result<Kr, KrEvalError> { float(x, f64::asin) }
+pub fn acos(x: &Kr) -> Result<Kr, KrEvalError> { float(x, f64::acos) }
+pub fn atan(x: &Kr) -> Result<Kr, KrEvalError> { float(x, f64::atan) }
+pub fn reciprocal(x: &Kr) -> Result<Kr, KrEvalError> { float(x, f64::recip) }
+
+// A float rounded to a long, null if it is null or out of range
+fn to_long(f: f64) -> i64 {
+    if f.is_finite() && f.abs() < i64::MAX as f64 { f as i64 } else { NULL_J }
+}
+
+fn round(x: &Kr, f: fn(f64) -> f64) -> Result<Kr, KrEvalError> {
+    list::atomic1(x, &|a| match a {
+        Kr::I(_) | Kr::J(_) => Ok(a.clone()),
+        Kr::E(n) => Ok(Kr::J(to_long(f(*n as f64)))),
+        Kr::F(n) => Ok(Kr::J(to_long(f(*n)))),
+        _ => Ok(Kr::J(to_long(f(cast::real(&number(a)?))))),
+    })
+}
+
+pub fn floor(x: &Kr) -> Result<Kr, KrEvalError> { round(x, f64::floor) }
+pub fn ceiling(x: &Kr) -> Result<Kr, KrEvalError> { round(x, f64::ceil) }
+
+// Apply f keeping the type of the number, nulls stay null
+fn signed(x: &Kr, int: fn(i64) -> i64, float: fn(f64) -> f64) -> Result<Kr, KrEvalError> {
+    list::atomic1(x, &|a| match a {
+        Kr::I(NULL_I) | Kr::J(NULL_J) => Ok(a.clone()),
+        Kr::I(n) => Ok(Kr::I(int(*n as i64) as i32)),
+        Kr::J(n) => Ok(Kr::J(int(*n))),
+        Kr::E(n) => Ok(Kr::E(float(*n as f64) as f32)),
+        Kr::F(n) => Ok(Kr::F(float(*n))),
+        _ => match number(a)? {
+            Num::Int(n) => Ok(Kr::J(int(n))),
+            _ => Err(KrEvalError::type_of(&[a])),
+        },
+    })
+}
+
+pub fn abs(x: &Kr) -> Result<Kr, KrEvalError> { signed(x, i64::abs, f64::abs) }
+pub fn neg(x: &Kr) -> Result<Kr, KrEvalError> { signed(x, |n| -n, |n| -n) }
+
+pub fn signum(x: &Kr) -> Result<Kr, KrEvalError> {
+    list::atomic1(x, &|a| Ok(Kr::I(match number(a)? {
+        Num::Null => NULL_I,
+        Num::Int(n) => n.signum() as i32,
+        Num::Float(0.0) => 0,
+        Num::Float(f) => f.signum() as i32,
+    })))
+}
+
+pub fn xexp(x: &Kr, y: &Kr) -> Result<Kr, KrEvalError> {
+    list::atomic2(x, y, &|a, b| Ok(Kr::F(cast::real(&number(a)?).powf(cast::real(&number(b)?)))))
+}
+
+pub fn xlog(x: &Kr, y: &Kr) -> Result<Kr, KrEvalError> {
+    list::atomic2(x, y, &|a, b| Ok(Kr::F(cast::real(&number(b)?).log(cast::real(&number(a)?)))))
+}
+
+// Integer result of mod and div in the wider type of the arguments
+fn integer(a: &Kr, b: &Kr, n: i64) -> Kr {
+    match (a, b) {
+        (Kr::I(_), Kr::I(_)) => Kr::I(if n == NULL_J { NULL_I } else { n as i32 }),
+        _ => Kr::J(n),
+    }
+}
+
+// Quotient rounded down rather than towards zero
+fn floor_div(m: i64, n: i64) -> i64 {
+    let q = m / n;
+    if m % n != 0 && (m < 0) != (n < 0) { q - 1 } else { q }
+}
+
+fn is_float(x: &Kr) -> bool {
+    matches!(x, Kr::E(_) | Kr::F(_))
+}
+
+// Integers divided by 0 give null, floats follow the float rules
+fn divide(x: &Kr, y: &Kr, int: fn(i64, i64) -> i64, float: fn(f64, f64) -> f64) -> Result<Kr, KrEvalError> {
+    list::atomic2(x, y, &|a, b| {
+        let (m, n) = (number(a)?, number(b)?);
+        if is_float(a) || is_float(b) { return Ok(Kr::F(float(cast::real(&m), cast::real(&n)))) };
+        match (m, n) {
+            (Num::Int(m), Num::Int(n)) if n != 0 => Ok(integer(a, b, int(m, n))),
+            _ => Ok(integer(a, b, NULL_J)),
+        }
+    })
+}
+
+pub fn div(x: &Kr, y: &Kr) -> Result<Kr, KrEvalError> {
+    divide(x, y, floor_div, |m, n| (m / n).floor())
+}
+
+pub fn modulo(x: &Kr, y: &Kr) -> Result<Kr, KrEvalError> {
+    divide(x, y, |m, n| m - n * floor_div(m, n), |m, n| m - n * (m / n).floor())
+}
+
+// Pairs of weights and values that are both present
+fn weighted(w: &Kr, x: &Kr) -> Result<Vec<(Num, Num)>, KrEvalError> {
+    let (ws, xs) = match (w.count(), x.count()) {
+        (Some(n), Some(m)) if n != m => return Err(KrEvalError::Length),
+        (None, None) => (vec![w.clone()], vec![x.clone()]),
+        (None, Some(m)) => (vec![w.clone(); m], x.items()),
+        (Some(n), None) => (w.items(), vec![x.clone(); n]),
+        (Some(_), Some(_)) => (w.items(), x.items()),
+    };
+    let mut pairs = Vec::new();
+    for (a, b) in ws.iter().zip(xs.iter()) {
+        match (number(a)?, number(b)?) {
+            (Num::Null, _) | (_, Num::Null) => {},
+            pair => pairs.push(pair),
+        }
+    }
+    Ok(pairs)
+}
+
+pub fn wsum(w: &Kr, x: &Kr) -> Result<Kr, KrEvalError> {
+    let pairs = weighted(w, x)?;
+    if pairs.iter().all(|p| matches!(p, (Num::Int(_), Num::Int(_)))) {
+        let sum = pairs.iter().try_fold(0i64, |s, p| match p {
+            (Num::Int(a), Num::Int(b)) => s.checked_add(a.checked_mul(*b)?),
+            _ => None,
+        });
+        return Ok(Kr::J(sum.unwrap_or(NULL_J)));
+    }
+    Ok(Kr::F(pairs.iter().map(|(a, b)| cast::real(a) * cast::real(b)).sum()))
+}
+
+pub fn wavg(w: &Kr, x: &Kr) -> Result<Kr, KrEvalError> {
+    let pairs = weighted(w, x)?;
+    let total: f64 = pairs.iter().map(|(a, _)| cast::real(a)).sum();
+    let sum: f64 = pairs.iter().map(|(a, b)| cast::real(a) * cast::real(b)).sum();
+    Ok(Kr::F(sum / total))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+    use crate::kr::{Kr, NULL_I, NULL_J};
+
+    // mod takes the sign of y, div rounds down
+    #[test]
+    fn mod_and_div_signs() {
+        assert_eq!(run(&["mod[-7;3]"]), Some(Kr::J(2)));
+        assert_eq!(run(&["mod[7;-3]"]), Some(Kr::J(-2)));
+        assert_eq!(run(&["div[-7;3]"]), Some(Kr::J(-3)));
+        assert_eq!(run(&["div[7;-3]"]), Some(Kr::J(-3)));
+        assert_eq!(run(&["mod[-7.5;2]"]), Some(Kr::F(0.5)));
+    }
+
+    #[test]
+    fn zero_divisors_give_nulls() {
+        assert_eq!(run(&["div[7;0]"]), Some(Kr::J(NULL_J)));
+        assert_eq!(run(&["mod[7;0]"]), Some(Kr::J(NULL_J)));
+        assert_eq!(run(&["mod[7i;0i]"]), Some(Kr::I(NULL_I)));
+    }
+}
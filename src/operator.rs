@@ -1,46 +1,113 @@
+use crate::attr;
+use crate::attr::Attr;
+use crate::cast;
 use crate::error::KrEvalError;
-use crate::kr::Kr;
+use crate::enums;
+use crate::index;
+use crate::kr::{Kr, KrFn, NULL_I, NULL_J};
 use crate::init::Env;
+use crate::list;
+use crate::list::atom_to_vec;
+use crate::random;
 use crate::text::Text;
+use crate::token::Span;
 
+use std::cmp::Ordering;
 use std::fmt::Debug;
 
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq)]
 pub enum Op {
     Addition,
     Subtraction,
     Multiplication,
     Division,
     Assign,
+    AssignGlobal,
     Join,
+    Take,
+    Drop,
+    Cast,
+    Dict,
+    At,
+    Dot,
+    Equal,
+    Less,
+    Greater,
+    Find,
 }
 
 #[derive(Clone, Debug)]
 pub struct Operator {
-    dyadic: fn(Env, &[Kr]) -> (Env, Result<Kr, KrEvalError>),
+    op: Op,
+    dyadic: KrFn,
+    variadic: Option<KrFn>,
     text: Text,
     rank: usize,
+    span: Option<Span>,     // Where it was written, for error messages
 }
 
 impl Operator {
     pub fn new(op: Op) -> Self {
-        let (f, t): (fn(Env, &[Kr]) -> (Env, Result<Kr, KrEvalError>), &str) = match op {
+        let (f, t): (KrFn, &str) = match op {
             Op::Addition => { (kr_addition, "+") },
             Op::Subtraction => { (kr_subtraction, "-") },
             Op::Multiplication => { (kr_multiplication, "*") },
             Op::Division => { (kr_division, "%") },
             Op::Assign => { (kr_assign, ":") },
+            Op::AssignGlobal => { (kr_assign_global, "::") },
             Op::Join => { (kr_join, ",") },
+            Op::Take => { (kr_take, "#") },
+            Op::Drop => { (kr_drop, "_") },
+            Op::Cast => { (kr_cast, "$") },
+            Op::Dict => { (kr_dict, "!") },
+            Op::At => { (kr_at, "@") },
+            Op::Dot => { (kr_dot, ".") },
+            Op::Equal => { (kr_equal, "=") },
+            Op::Less => { (kr_less, "<") },
+            Op::Greater => { (kr_greater, ">") },
+            Op::Find => { (kr_find, "?") },
         };
-        Operator { dyadic: f, text: Text::from_str(t), rank: 2 }
+        // Forms taking other than two arguments: @[x;i;f;y]
+        let variadic: Option<KrFn> = match op {
+            Op::Assign | Op::AssignGlobal => Some(kr_assign_at),
+            Op::At => Some(kr_amend),
+            Op::Dot => Some(kr_amend_deep),
+            _ => None,
+        };
+        Operator { op, dyadic: f, variadic, text: Text::from_str(t), rank: 2, span: None }
+    }
+
+    // Verbs that store, search or slice their arguments see their attributes
+    fn keeps_attrs(&self) -> bool {
+        matches!(self.op, Op::Assign | Op::AssignGlobal | Op::Take | Op::Drop | Op::Dict | Op::At | Op::Dot | Op::Equal | Op::Find)
     }
 
-    pub fn to_string(&self) -> String {
-        self.text.to_string()
+    pub fn op(&self) -> Op {
+        self.op
     }
+
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    pub fn with_span(self, span: Span) -> Self {
+        Operator { span: Some(span), ..self }
+    }
+
     pub fn apply(&self, env: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
-        if args.len() != self.rank { return (env, Err(KrEvalError::Rank)) };
-        (self.dyadic)(env, args)
+        let args = if self.keeps_attrs() { std::borrow::Cow::Borrowed(args) } else { attr::strip(args) };
+        let args = &args[..];
+        match self.variadic {
+            _ if args.len() == self.rank => (self.dyadic)(env, args),
+            Some(f) => f(env, args),
+            None => (env, Err(KrEvalError::Rank)),
+        }
+    }
+}
+
+impl std::fmt::Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.text)
     }
 }
 
@@ -76,63 +143,288 @@ macro_rules! elementwise_operation {
     };
 }
 
+// Integers wrap around on overflow rather than panicking
 macro_rules! generate_arithmetic_fn {
-    ($name:ident, $operator:tt) => {
+    ($name:ident, $int:ident, $operator:tt) => {
         fn $name(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
             let [x, y] = args else { unreachable!() };
             let res = match (x, y) {
-                (Kr::I(x), Kr::I(y)) => Ok(Kr::I(x $operator y)),
-                (Kr::J(x), Kr::J(y)) => Ok(Kr::J(x $operator y)),
+                (Kr::I(x), Kr::I(y)) => Ok(Kr::I(x.$int(*y))),
+                (Kr::J(x), Kr::J(y)) => Ok(Kr::J(x.$int(*y))),
                 (Kr::E(x), Kr::E(y)) => Ok(Kr::E(x $operator y)),
                 (Kr::F(x), Kr::F(y)) => Ok(Kr::F(x $operator y)),
-                (Kr::Iv(xv), Kr::Iv(yv)) => elementwise_operation!(Iv, xv, yv, |a, b| a $operator b),
-                (Kr::Jv(xv), Kr::Jv(yv)) => elementwise_operation!(Jv, xv, yv, |a, b| a $operator b),
+                (Kr::Iv(xv), Kr::Iv(yv)) => elementwise_operation!(Iv, xv, yv, |a: &i32, b: &i32| a.$int(*b)),
+                (Kr::Jv(xv), Kr::Jv(yv)) => elementwise_operation!(Jv, xv, yv, |a: &i64, b: &i64| a.$int(*b)),
                 (Kr::Ev(xv), Kr::Ev(yv)) => elementwise_operation!(Ev, xv, yv, |a, b| a $operator b),
                 (Kr::Fv(xv), Kr::Fv(yv)) => elementwise_operation!(Fv, xv, yv, |a, b| a $operator b),
-                (_, _) => Err(KrEvalError::Type),
+                (_, _) => Err(KrEvalError::type_of(&[x, y])),
             };
             (e, res)
         }
     };
 }
 
-generate_arithmetic_fn!(kr_addition, +);
-generate_arithmetic_fn!(kr_subtraction, -);
-generate_arithmetic_fn!(kr_multiplication, *);
-generate_arithmetic_fn!(kr_division, /);
+generate_arithmetic_fn!(kr_addition, wrapping_add, +);
+generate_arithmetic_fn!(kr_subtraction, wrapping_sub, -);
+generate_arithmetic_fn!(kr_multiplication, wrapping_mul, *);
+
+// Integers as floats for division, their nulls become float nulls
+fn int_float(n: i32) -> f64 {
+    if n == NULL_I { f64::NAN } else { n as f64 }
+}
+
+fn long_float(n: i64) -> f64 {
+    if n == NULL_J { f64::NAN } else { n as f64 }
+}
+
+// x%y always divides as floats, so dividing by 0 gives infinity, or null for 0%0
+fn kr_division(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let [x, y] = args else { unreachable!() };
+    let res = match (x, y) {
+        (Kr::I(x), Kr::I(y)) => Ok(Kr::F(int_float(*x) / int_float(*y))),
+        (Kr::J(x), Kr::J(y)) => Ok(Kr::F(long_float(*x) / long_float(*y))),
+        (Kr::E(x), Kr::E(y)) => Ok(Kr::E(x / y)),
+        (Kr::F(x), Kr::F(y)) => Ok(Kr::F(x / y)),
+        (Kr::Iv(xv), Kr::Iv(yv)) => elementwise_operation!(Fv, xv, yv, |a: &i32, b: &i32| int_float(*a) / int_float(*b)),
+        (Kr::Jv(xv), Kr::Jv(yv)) => elementwise_operation!(Fv, xv, yv, |a: &i64, b: &i64| long_float(*a) / long_float(*b)),
+        (Kr::Ev(xv), Kr::Ev(yv)) => elementwise_operation!(Ev, xv, yv, |a, b| a / b),
+        (Kr::Fv(xv), Kr::Fv(yv)) => elementwise_operation!(Fv, xv, yv, |a, b| a / b),
+        (_, _) => Err(KrEvalError::type_of(&[x, y])),
+    };
+    (e, res)
+}
 
 fn kr_join(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
     let [x, y] = args else { unreachable!() };
     let x = atom_to_vec(x);
     let y = atom_to_vec(y);
     let res = match (x,y) {
+        (Kr::Bv(x), Kr::Bv(y)) => Ok(Kr::Bv([&x[..], &y[..]].concat())),
         (Kr::Iv(x), Kr::Iv(y)) => Ok(Kr::Iv([&x[..], &y[..]].concat())),
         (Kr::Jv(x), Kr::Jv(y)) => Ok(Kr::Jv([&x[..], &y[..]].concat())),
         (Kr::Ev(x), Kr::Ev(y)) => Ok(Kr::Ev([&x[..], &y[..]].concat())),
         (Kr::Fv(x), Kr::Fv(y)) => Ok(Kr::Fv([&x[..], &y[..]].concat())),
         (Kr::Cv(x), Kr::Cv(y)) => Ok(Kr::Cv([&x[..], &y[..]].concat())),
+        (Kr::Sv(x), Kr::Sv(y)) => Ok(Kr::Sv([&x[..], &y[..]].concat())),
+        (Kr::Enumv(d, x), Kr::Enumv(d2, y)) if d.name() == d2.name() => Ok(match d.latest(&d2) {
+            Some(d) => Kr::Enumv(d, [&x[..], &y[..]].concat()),
+            None => Kr::Sv(x.iter().map(|i| d.sym(*i)).chain(y.iter().map(|i| d2.sym(*i))).collect()),
+        }),
         (Kr::NN(x), Kr::NN(y)) => Ok(Kr::NN([&x[..], &y[..]].concat())),
-        (_, _) => Err(KrEvalError::Type)
+        (Kr::Op(_), _) | (Kr::Prim(_), _) | (Kr::Lambda(_), _) | (_, Kr::Op(_)) | (_, Kr::Prim(_)) | (_, Kr::Lambda(_)) => Err(KrEvalError::type_of(&args.iter().collect::<Vec<&Kr>>())),
+        (x, y) => Ok(Kr::NN([x.items(), y.items()].concat())),
     };
     (e, res)
 }
 
 fn kr_assign(mut e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
     let [x, y] = args else { unreachable!() };
-    match (x,y) {
-        (Kr::S(k), v) => {e.var.insert(k.clone(), v.clone());},
-        (_, _) => return (e, Err(KrEvalError::Assign),)
+    let res = match (x,y) {
+        (Kr::S(k), Kr::Lambda(l)) => e.set(k.name(), Kr::Lambda(l.named(&k.text()))),
+        (Kr::S(k), v) => e.set(k.name(), v.clone()),
+        (_, _) => Err(KrEvalError::Assign),
+    };
+    (e, res.map(|_| Kr::Null))
+}
+
+// x::y always assigns to a global, even inside a function
+fn kr_assign_global(mut e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let [x, y] = args else { unreachable!() };
+    let res = match (x,y) {
+        (Kr::S(k), Kr::Lambda(l)) => e.set_global(k.name(), Kr::Lambda(l.named(&k.text()))),
+        (Kr::S(k), v) => e.set_global(k.name(), v.clone()),
+        (_, _) => Err(KrEvalError::Assign),
+    };
+    (e, res.map(|_| Kr::Null))
+}
+
+// n#y takes n items of y, a shape vector reshapes y
+// `s#y applies an attribute to y, `#y removes it
+fn kr_take(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let [x, y] = args else { unreachable!() };
+    let res = match x {
+        Kr::S(name) if name.is_empty() => Ok(y.plain().clone()),
+        Kr::S(name) => match Attr::from_name(name.name()) {
+            Some(a) => attr::apply(a, y),
+            None => Err(KrEvalError::Domain),
+        },
+        Kr::I(_) | Kr::J(_) => {
+            let n = list::as_long(x).unwrap();
+            // Taking no more than the whole list is a range of it
+            let range = n.unsigned_abs() <= y.count().unwrap_or(0) as u64;
+            list::take(n, y.plain()).map(|r| if range { attr::keep_range(y, r) } else { r })
+        },
+        Kr::Iv(_) | Kr::Jv(_) => list::reshape(&list::as_longs(x).unwrap(), y.plain()),
+        _ => Err(KrEvalError::type_of(&[x, y])),
+    };
+    (e, res)
+}
+
+// n_y drops n items of y, a vector of indices cuts y
+fn kr_drop(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let [x, y] = args else { unreachable!() };
+    let res = match x {
+        Kr::I(_) | Kr::J(_) => list::drop(list::as_long(x).unwrap(), y.plain()).map(|r| attr::keep_range(y, r)),
+        Kr::Iv(_) | Kr::Jv(_) => list::cut(&list::as_longs(x).unwrap(), y.plain()),
+        _ => Err(KrEvalError::type_of(&[x, y])),
+    };
+    (e, res)
+}
+
+// `d$y enumerates y when d is not a type but the name of a domain
+fn kr_cast(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let [x, y] = args else { unreachable!() };
+    if let Kr::S(name) = x {
+        if !cast::is_type_name(name.name()) {
+            let res = enums::enumerate(&e, *name, y);
+            return (e, res);
+        }
     }
-    (e, Ok(Kr::Null))
+    (e, cast::cast(x, y))
 }
 
-fn atom_to_vec(x: &Kr) -> Kr {
-    match x {
-        Kr::I(a) => Kr::Iv([*a].to_vec()),
-        Kr::J(a) => Kr::Jv([*a].to_vec()),
-        Kr::E(a) => Kr::Ev([*a].to_vec()),
-        Kr::F(a) => Kr::Fv([*a].to_vec()),
-        Kr::C(a) => Kr::Cv([*a].to_vec()),
-        other => other.clone(),
+// x?y finds the index of y in x, the count of x if it is missing
+// `d?y enumerates y against the domain d, extending it as needed
+// n?y draws n random items from y
+fn kr_find(mut e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let [x, y] = args else { unreachable!() };
+    let n = match x {
+        Kr::S(name) => return enums::extend(e, *name, y.plain()),
+        Kr::I(_) | Kr::J(_) => {
+            let n = match x { Kr::I(NULL_I) => NULL_J, _ => list::as_long(x).unwrap() };
+            let res = random::random(&mut e.rng, n, y.plain());
+            return (e, res);
+        },
+        _ => match x.count() {
+            Some(n) => n,
+            None => return (e, Err(KrEvalError::type_of(&[x, y]))),
+        },
+    };
+    let position = |k: &Kr| Kr::J(x.find(k).unwrap_or(n) as i64);
+    let y = y.plain();
+    let res = match y.count() {
+        None => position(y),
+        Some(_) => Kr::Jv(y.items().iter().map(|k| match position(k) { Kr::J(i) => i, _ => unreachable!() }).collect()),
+    };
+    (e, Ok(res))
+}
+
+// Keys!values
+fn kr_dict(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let [x, y] = args else { unreachable!() };
+    let keys = atom_to_vec(x);
+    let values = atom_to_vec(y);
+    let res = match (keys.count(), values.count()) {
+        (Some(n), Some(m)) if n == m => Ok(Kr::Dict(Box::new(keys), Box::new(values))),
+        (Some(_), Some(_)) => Err(KrEvalError::Length),
+        (_, _) => Err(KrEvalError::type_of(&[x, y])),
+    };
+    (e, res)
+}
+
+// f@x applies f to x, or indexes a list or dictionary
+fn kr_at(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let [x, y] = args else { unreachable!() };
+    x.apply(e, std::slice::from_ref(y))
+}
+
+// f . x applies f to each item of x as an argument, or indexes at depth
+fn kr_dot(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let [x, y] = args else { unreachable!() };
+    x.apply(e, &y.items())
+}
+
+// @[x;i;f] and @[x;i;f;y]
+// @[f;x;h] is protected application instead when f is a function
+fn kr_amend(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    match args {
+        [f, x, h] if f.is_function() => trap(e, f, std::slice::from_ref(x), h),
+        [x, i, f] => index::amend(e, x, std::slice::from_ref(i), f, None),
+        [x, i, f, y] => index::amend(e, x, std::slice::from_ref(i), f, Some(y)),
+        _ => (e, Err(KrEvalError::Rank)),
     }
-}
\ No newline at end of file
+}
+
+// .[x;p;f] and .[x;p;f;y]
+// .[f;args;h] applies f to each item of args, protected
+fn kr_amend_deep(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    match args {
+        [f, x, h] if f.is_function() => trap(e, f, &x.items(), h),
+        [x, p, f] => index::amend(e, x, &p.items(), f, None),
+        [x, p, f, y] => index::amend(e, x, &p.items(), f, Some(y)),
+        _ => (e, Err(KrEvalError::Rank)),
+    }
+}
+
+// Apply f, an error gives h applied to its message, or h itself if h is not a function
+// Interrupts and exits are not caught so Ctrl-C and \\ always reach the REPL
+fn trap(mut e: Env, f: &Kr, args: &[Kr], h: &Kr) -> (Env, Result<Kr, KrEvalError>) {
+    // The trace is thrown away, so it is not built
+    let tracing = e.set_tracing(false);
+    let (mut e, res) = f.apply(e, args);
+    e.set_tracing(tracing);
+    match (e, res) {
+        (mut e, Err(err)) if err.trappable() => {
+            e.take_site();
+            e.take_trace();
+            let msg = Kr::Cv(err.message().into_bytes());
+            if h.is_function() { h.apply(e, &[msg]) } else { (e, Ok(h.clone())) }
+        },
+        res => res,
+    }
+}
+
+// :[`name;p;f;y] assigns f[name . p;y] at path p within a variable, used for name[i]:y
+// A null path assigns to the whole variable, used for name+:y
+// Inside a function the variable is local, only name[i]::y with f as :: amends a global
+fn kr_assign_at(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let [Kr::S(name), p, f, y] = args else { return (e, Err(KrEvalError::Assign)) };
+    let path = match p {
+        Kr::Null => Vec::new(),
+        p => p.items(),
+    };
+    let global = matches!(f, Kr::Op(op) if op.op() == Op::AssignGlobal);
+    let (e, res) = index::amend_var(e, name.name(), &path, f, Some(y), global);
+    (e, res.map(|_| Kr::Null))
+}
+
+// Order of two atoms, numbers compare across types and nulls are less than any other value
+pub fn atom_cmp(x: &Kr, y: &Kr) -> Option<Ordering> {
+    use cast::Num;
+    let (x, y) = (x.symbols().unwrap_or_else(|| x.clone()), y.symbols().unwrap_or_else(|| y.clone()));
+    let (x, y) = (&x, &y);
+    if let (Kr::S(a), Kr::S(b)) = (x, y) { return Some(a.cmp_text(b)) };
+    let ord = match (cast::num(x)?, cast::num(y)?) {
+        (Num::Null, Num::Null) => Ordering::Equal,
+        (Num::Null, _) => Ordering::Less,
+        (_, Num::Null) => Ordering::Greater,
+        (Num::Int(a), Num::Int(b)) => a.cmp(&b),
+        (a, b) => cast::real(&a).partial_cmp(&cast::real(&b))?,
+    };
+    Some(ord)
+}
+
+macro_rules! generate_comparison_fn {
+    ($name:ident, $test:expr) => {
+        fn $name(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+            let [x, y] = args else { unreachable!() };
+            let res = list::atomic2(x, y, &|a, b| {
+                atom_cmp(a, b).map(|o| Kr::B($test(o))).ok_or(KrEvalError::type_of(&[a, b]))
+            });
+            (e, res)
+        }
+    };
+}
+
+generate_comparison_fn!(kr_equal_items, |o| o == Ordering::Equal);
+generate_comparison_fn!(kr_less, |o| o == Ordering::Less);
+generate_comparison_fn!(kr_greater, |o| o == Ordering::Greater);
+
+// x=y finds the matches through an attribute rather than comparing every item
+fn kr_equal(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let [x, y] = args else { unreachable!() };
+    match attr::equal(x, y) {
+        Some(res) => (e, Ok(res)),
+        None => kr_equal_items(e, &attr::strip(args)),
+    }
+}
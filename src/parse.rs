@@ -1,88 +1,251 @@
+use crate::operator::{Op, Operator};
 use crate::primitive::{Prim, Primitive};
-use crate::token::{Token, KrToken};
+use crate::token::{Token, KrToken, Lexed, Span};
 use crate::kr::Kr;
 use crate::error::KrParseError;
+use crate::lambda::Lambda;
+use crate::sym::Sym;
+use crate::text::Text;
 
 /*
 Grammar:
 prog := <expr> <;> <prog>                       // nyi
       | <expr>                                  // nyi
-expr := <term> <op> <expr>
+expr := <name> <:> <expr>
+      | <name> <[> <expr> {<;> <expr> } <]> <:> <expr>
+      | <name> <op:> <expr>
+      | <name> <[> <expr> {<;> <expr> } <]> <op:> <expr>
+      | <term> <op> <expr>
       | <term> <expr>
       | <term>
 term := <kr>
       | <(> <expr> <)> 
       | <[> <expr> {<;> <expr> } <]>
+      | <term> <[> <expr> {<;> <expr> } <]>     // application, [ must follow term directly
+      | <{> [<[> <name> {<;> <name> } <]>] <expr> {<;> <expr> } <}>
 */
 
 
-pub fn parse(tokens:&[Token]) -> Result<Kr, KrParseError> {
-    if tokens.len() == 0 { return Ok(Kr::Null) };
-    let (res, n) = parse_expr(tokens, 0)?;
+// Errors carry the index of the token where parsing failed
+type ParseResult<T> = Result<T, (KrParseError, usize)>;
+
+// Operators, primitives and names in the AST keep the span they were written at
+// so that eval errors can point back at them
+pub fn parse(tokens:&Lexed) -> Result<Kr, (KrParseError, Span)> {
+    if tokens.is_empty() { return Ok(Kr::Null) };
+    let (res, n) = parse_expr(tokens, 0).map_err(|(err, i)| (err, tokens.span(i)))?;
     if n == tokens.len() {
         Ok(res)
     } else {
-        Err(KrParseError::IncompleteParse)
+        Err((KrParseError::IncompleteParse, tokens.span(n)))
     }
 }
 
-fn parse_expr(tokens:&[Token], i: usize) -> Result<(Kr, usize), KrParseError> {
+fn parse_expr(tokens:&Lexed, i: usize) -> ParseResult<(Kr, usize)> {
+    if let Some(assign) = parse_assign(tokens, i)? { return Ok(assign) };
     // First part of a expression will always be a term
     let (term, j) = parse_term(tokens, i)?;
     // Check next token
     let next_token = tokens.get(j);
     match next_token {
+        Some(Token::KrToken(KrToken::Operator(op))) if op.is_compound() => Err((KrParseError::InvalidAssign, j)),
         // <term> <op> <expr>
         Some(&Token::KrToken(KrToken::Operator(ref op))) => {
             let (expr , k) = parse_expr(tokens, j+1)?;
-            Ok((Kr::NN(vec![Kr::Op(op.parse()), term, expr]), k))
+            Ok((Kr::NN(vec![Kr::Op(op.parse().with_span(tokens.span(j))), term, expr]), k))
         }
         // <term> <expr>
-        Some(Token::KrToken(_)) | Some(Token::LParen) | Some(Token::LBracket) => {
+        Some(Token::KrToken(_)) | Some(Token::LParen) | Some(Token::LBracket) | Some(Token::LApply) | Some(Token::LBrace) => {
             let (expr, k) = parse_expr(tokens, j)?;
             Ok((Kr::NN(vec![term, expr]), k))
         }
         // <term>
-        None | Some(Token::RParen) | Some(Token::RBracket) => { Ok((term, j)) },
+        None | Some(Token::RParen) | Some(Token::RBracket) | Some(Token::RBrace) => { Ok((term, j)) },
         Some(Token::SemiColon) => Ok((term, j)),
     }
 }
 
-fn parse_term(tokens:&[Token], i: usize) -> Result<(Kr, usize), KrParseError> {
-    let t = tokens.get(i).ok_or(KrParseError::UnexpectedEOF)?;
+// Assignment to a name, or to items of a name: x:y and x[i]:y
+// The name is not evaluated, x[i]:y amends x in place with :[`x;[i];:;y]
+// Compound assignment x+:y applies + then assigns with :[`x;::;+;y]
+fn parse_assign(tokens:&Lexed, i: usize) -> ParseResult<Option<(Kr, usize)>> {
+    let Some(Token::KrToken(KrToken::Name(name))) = tokens.get(i) else { return Ok(None) };
+    let (indices, j) = match tokens.get(i+1) {
+        Some(Token::LApply) => {
+            let (args, j) = parse_list(tokens, i+2)?;
+            (Some(args), j)
+        },
+        _ => (None, i+1),
+    };
+    let Some(Token::KrToken(KrToken::Operator(op))) = tokens.get(j) else { return Ok(None) };
+    let kind = match op.parse().op() {
+        Op::AssignGlobal => Op::AssignGlobal,
+        _ if op.is_compound() => Op::Assign,
+        Op::Assign => Op::Assign,
+        _ => return Ok(None),
+    };
+    let (expr, k) = parse_expr(tokens, j+1)?;
+    let assign = Kr::Op(Operator::new(kind).with_span(tokens.span(j)));
+    let f = if op.is_compound() { Kr::Op(op.parse().with_span(tokens.span(j))) } else { assign.clone() };
+    let ast = match indices {
+        None if !op.is_compound() => vec![assign, Kr::S(Sym::from_text(&name.text())), expr],
+        None => vec![assign, Kr::S(Sym::from_text(&name.text())), Kr::Null, f, expr],
+        Some(args) => {
+            let path = Kr::NN([vec![Kr::Prim(Primitive::new(Prim::Enlist))], args].concat());
+            vec![assign, Kr::S(Sym::from_text(&name.text())), path, f, expr]
+        },
+    };
+    Ok(Some((Kr::NN(ast), k)))
+}
+
+fn parse_term(tokens:&Lexed, i: usize) -> ParseResult<(Kr, usize)> {
+    let (mut term, mut j) = parse_atom(tokens, i)?;
+    // f[x;y] applies f to each expression between the brackets
+    while let Some(Token::LApply) = tokens.get(j) {
+        let (args, k) = parse_list(tokens, j+1)?;
+        term = Kr::NN([vec![term], args].concat());
+        j = k;
+    }
+    Ok((term, j))
+}
+
+fn parse_atom(tokens:&Lexed, i: usize) -> ParseResult<(Kr, usize)> {
+    let t = tokens.get(i).ok_or((KrParseError::UnexpectedEOF, i))?;
     match t {
-        Token::KrToken(kr) => Ok((kr.parse(), i+1)),
+        Token::KrToken(KrToken::Operator(op)) if op.is_compound() => Err((KrParseError::InvalidAssign, i)),
+        Token::KrToken(kr) => Ok((spanned(kr.parse(), tokens.span(i)), i+1)),
         Token::LParen => {
             let (kr, k) = parse_expr(tokens, i+1)?;
             if let Some(&Token::RParen) = tokens.get(k) {
                 Ok((kr, k+1))
             } else {
-                Err(KrParseError::MissingRParen)
+                Err((KrParseError::MissingRParen, k))
             }
         }
-        Token::RParen => Err(KrParseError::UnexpectedRParen),
-        Token::LBracket => {
-            let mut elements: Vec<Kr> = vec![Kr::Prim(Primitive::new(Prim::Enlist))];
-            let mut j = i + 1;
-            loop {
-                let (expr, k) = parse_expr(tokens, j)?;
-                elements.push(expr);
-                if let Some(Token::SemiColon) = tokens.get(k) {
-                    j = k + 1;
-                    continue;
-                } else {
-                    j = k;
-                    break;
-                }
-            }
-            if let Some(&Token::RBracket) = tokens.get(j) {
-                Ok((Kr::NN(elements), j+1))
-            } else {
-                Err(KrParseError::MissingRBracket)
-            } 
+        Token::RParen => Err((KrParseError::UnexpectedRParen, i)),
+        Token::LBracket | Token::LApply => {
+            let (elements, k) = parse_list(tokens, i+1)?;
+            let enlist = Kr::Prim(Primitive::new(Prim::Enlist).with_span(tokens.span(i)));
+            Ok((Kr::NN([vec![enlist], elements].concat()), k))
+        }
+        Token::RBracket => Err((KrParseError::UnexpectedRBracket, i)),
+        Token::LBrace => parse_lambda(tokens, i),
+        Token::RBrace => Err((KrParseError::UnexpectedRBrace, i)),
+        Token::SemiColon => Err((KrParseError::UnexpectedSemiColon, i)),
+    }
+}
+
+// Expressions separated by ; up to and including the closing ]
+fn parse_list(tokens:&Lexed, i: usize) -> ParseResult<(Vec<Kr>, usize)> {
+    let mut elements: Vec<Kr> = Vec::new();
+    let mut j = i;
+    loop {
+        // An empty element such as f[] or x[;1] is ::
+        let (expr, k) = match tokens.get(j) {
+            Some(Token::SemiColon) | Some(Token::RBracket) => (Kr::Null, j),
+            _ => parse_expr(tokens, j)?,
+        };
+        elements.push(expr);
+        if let Some(Token::SemiColon) = tokens.get(k) {
+            j = k + 1;
+            continue;
+        } else {
+            j = k;
+            break;
+        }
+    }
+    if let Some(&Token::RBracket) = tokens.get(j) {
+        Ok((elements, j+1))
+    } else {
+        Err((KrParseError::MissingRBracket, j))
+    }
+}
+
+// A lambda {[a;b] a+b} from the { at i up to and including the matching }
+// Without a parameter list it takes x, y and z, as many as the body uses
+fn parse_lambda(tokens:&Lexed, i: usize) -> ParseResult<(Kr, usize)> {
+    let mut j = i + 1;
+    let explicit = match tokens.get(j) {
+        Some(Token::LBracket) => {
+            let (params, k) = parse_params(tokens, j+1)?;
+            j = k;
+            Some(params)
+        },
+        _ => None,
+    };
+    let mut body: Vec<Kr> = Vec::new();
+    loop {
+        let (statement, k) = match tokens.get(j) {
+            Some(Token::SemiColon) | Some(Token::RBrace) => (Kr::Null, j),
+            _ => parse_expr(tokens, j)?,
+        };
+        body.push(statement);
+        match tokens.get(k) {
+            Some(Token::SemiColon) => j = k + 1,
+            Some(Token::RBrace) => { j = k; break },
+            _ => return Err((KrParseError::MissingRBrace, k)),
+        }
+    }
+    let inner = &tokens.tokens[i+1..j];
+    let names: Vec<Text> = inner.iter().filter_map(|t| match t {
+        Token::KrToken(KrToken::Name(name)) => Some(name.text()),
+        _ => None,
+    }).collect();
+    let params = explicit.unwrap_or_else(|| implicit_params(inner));
+    // The lambda keeps its source text, spans in its body are offsets from the {
+    let (start, end) = (tokens.span(i).start, tokens.span(j).end);
+    let text = Text::from_slice(&tokens.source.0[start..end]);
+    Ok((Kr::Lambda(Lambda::new(params, body, names, text, start)), j+1))
+}
 
+// Names between [ and ], separated by ;
+fn parse_params(tokens:&Lexed, i: usize) -> ParseResult<(Vec<Text>, usize)> {
+    let mut params: Vec<Text> = Vec::new();
+    let mut j = i;
+    loop {
+        match (tokens.get(j), tokens.get(j+1)) {
+            (Some(Token::RBracket), _) if params.is_empty() => return Ok((params, j+1)),
+            (Some(Token::KrToken(KrToken::Name(name))), Some(Token::SemiColon)) => params.push(name.text()),
+            (Some(Token::KrToken(KrToken::Name(name))), Some(Token::RBracket)) => {
+                params.push(name.text());
+                return Ok((params, j+2));
+            },
+            _ => return Err((KrParseError::InvalidParams, j)),
         }
-        Token::RBracket => Err(KrParseError::UnexpectedRBracket),
-        Token::SemiColon => Err(KrParseError::UnexpectedSemiColon),
+        j += 2;
+    }
+}
+
+// x, x y or x y z depending on the highest one used directly in the body
+fn implicit_params(tokens:&[Token]) -> Vec<Text> {
+    let mut depth = 0;
+    let mut rank = 1;
+    for t in tokens {
+        match t {
+            Token::LBrace => depth += 1,
+            Token::RBrace => depth -= 1,
+            Token::KrToken(KrToken::Name(name)) if depth == 0 => {
+                match name.text().0[..] {
+                    [b'y'] => rank = rank.max(2),
+                    [b'z'] => rank = 3,
+                    _ => {},
+                }
+            },
+            _ => {},
+        }
+    }
+    ["x", "y", "z"][..rank].iter().map(|p| Text::from_str(p)).collect()
+}
+
+// Attach a span to an operator, primitive or name reference
+fn spanned(kr: Kr, span: Span) -> Kr {
+    match kr {
+        Kr::Op(op) => Kr::Op(op.with_span(span)),
+        Kr::Prim(prim) => Kr::Prim(prim.with_span(span)),
+        Kr::NN(mut list) => {
+            if let Some(Kr::Prim(prim)) = list.first_mut() { *prim = prim.clone().with_span(span) };
+            Kr::NN(list)
+        },
+        other => other,
     }
 }
@@ -1,7 +1,15 @@
-use crate::error::KrEvalError;
-use crate::kr::Kr;
+use crate::attr;
+use crate::cast;
+use crate::error::{KrError, KrEvalError};
+use crate::kr::{Kr, KrFn};
 use crate::init::Env;
+use crate::list;
+use crate::math;
+use crate::strings;
+use crate::sym::Sym;
+use crate::system;
 use crate::text::Text;
+use crate::token::Span;
 
 /*
 List of built in primitive functions
@@ -14,41 +22,154 @@ pub enum Prim {
     Til,
     Enlist,
     Value,
+    Reverse,
+    Rotate,
+    Raze,
+    Flip,
+    String,
+    Type,
+    Key,
+    Do,
+    While,
+    If,
+    Signal,
+    Trap,
+    Like,
+    Ss,
+    Ssr,
+    Vs,
+    Sv,
+    Upper,
+    Lower,
+    Trim,
+    Ltrim,
+    Rtrim,
+    Attr,
+    In,
+    Bin,
+    Sqrt,
+    Exp,
+    Log,
+    Xexp,
+    Xlog,
+    Abs,
+    Neg,
+    Signum,
+    Floor,
+    Ceiling,
+    Reciprocal,
+    Mod,
+    Div,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Wsum,
+    Wavg,
+    System,
 }
 
 #[derive(Clone, Debug)]
 pub struct Primitive {
-    f: fn(Env, &[Kr]) -> (Env, Result<Kr, KrEvalError>),
+    prim: Prim,
+    f: KrFn,
     text: Text,
     rank: usize,
+    span: Option<Span>,     // Where it was written, for error messages
 }
 
 impl Primitive {
     pub fn new(prim: Prim) -> Self {
-        let (f, t, rank): (fn(Env, &[Kr]) -> (Env, Result<Kr, KrEvalError>), &str, usize) = match prim {
+        let (f, t, rank): (KrFn, &str, usize) = match prim {
             Prim::First => { (kr_first_wrapped, "first", 1) },
             Prim::Last => { (kr_last_wrapped, "last", 1) },
             Prim::Til => { (kr_til_wrapped, "til", 1) },
             Prim::Enlist => { (kr_enlist, "enlist", 0) },
-            Prim::Value => { (kr_value, "value", 1) }
+            Prim::Value => { (kr_value, "value", 1) },
+            Prim::Reverse => { (kr_reverse, "reverse", 1) },
+            Prim::Rotate => { (kr_rotate, "rotate", 2) },
+            Prim::Raze => { (kr_raze, "raze", 1) },
+            Prim::Flip => { (kr_flip, "flip", 1) },
+            Prim::String => { (kr_string, "string", 1) },
+            Prim::Type => { (kr_type, "type", 1) },
+            Prim::Key => { (kr_key, "key", 1) },
+            // Control words are evaluated by eval, they are never applied to values
+            Prim::Do => { (kr_control, "do", 0) },
+            Prim::While => { (kr_control, "while", 0) },
+            Prim::If => { (kr_control, "if", 0) },
+            Prim::Signal => { (kr_signal, "'", 1) },
+            Prim::Trap => { (kr_trap, "trp", 3) },
+            Prim::Like => { (kr_like, "like", 2) },
+            Prim::Ss => { (kr_ss, "ss", 2) },
+            Prim::Ssr => { (kr_ssr, "ssr", 3) },
+            Prim::Vs => { (kr_vs, "vs", 2) },
+            Prim::Sv => { (kr_sv, "sv", 2) },
+            Prim::Upper => { (kr_upper, "upper", 1) },
+            Prim::Lower => { (kr_lower, "lower", 1) },
+            Prim::Trim => { (kr_trim, "trim", 1) },
+            Prim::Ltrim => { (kr_ltrim, "ltrim", 1) },
+            Prim::Rtrim => { (kr_rtrim, "rtrim", 1) },
+            Prim::Attr => { (kr_attr, "attr", 1) },
+            Prim::In => { (kr_in, "in", 2) },
+            Prim::Bin => { (kr_bin, "bin", 2) },
+            Prim::Sqrt => { (kr_sqrt, "sqrt", 1) },
+            Prim::Exp => { (kr_exp, "exp", 1) },
+            Prim::Log => { (kr_log, "log", 1) },
+            Prim::Xexp => { (kr_xexp, "xexp", 2) },
+            Prim::Xlog => { (kr_xlog, "xlog", 2) },
+            Prim::Abs => { (kr_abs, "abs", 1) },
+            Prim::Neg => { (kr_neg, "neg", 1) },
+            Prim::Signum => { (kr_signum, "signum", 1) },
+            Prim::Floor => { (kr_floor, "floor", 1) },
+            Prim::Ceiling => { (kr_ceiling, "ceiling", 1) },
+            Prim::Reciprocal => { (kr_reciprocal, "reciprocal", 1) },
+            Prim::Mod => { (kr_mod, "mod", 2) },
+            Prim::Div => { (kr_div, "div", 2) },
+            Prim::Sin => { (kr_sin, "sin", 1) },
+            Prim::Cos => { (kr_cos, "cos", 1) },
+            Prim::Tan => { (kr_tan, "tan", 1) },
+            Prim::Asin => { (kr_asin, "asin", 1) },
+            Prim::Acos => { (kr_acos, "acos", 1) },
+            Prim::Atan => { (kr_atan, "atan", 1) },
+            Prim::Wsum => { (kr_wsum, "wsum", 2) },
+            Prim::Wavg => { (kr_wavg, "wavg", 2) },
+            Prim::System => { (kr_system, "system", 1) },
         };
-        Primitive { f, text: Text::from_str(t), rank}
+        Primitive { prim, f, text: Text::from_str(t), rank, span: None }
     }
     pub fn apply(&self, env: Env, args:&[Kr]) -> (Env, Result<Kr, KrEvalError>) {
         // Check rank, unless it is enlist
-        if (args.len() != self.rank) && (self.f != kr_enlist) {
+        if (args.len() != self.rank) && !matches!(self.prim, Prim::Enlist) {
             return (env, Err(KrEvalError::Rank))
         };
-        (self.f)(env, args)
+        // Only the primitives that search or report attributes see them
+        match self.prim {
+            Prim::Attr | Prim::In | Prim::Bin | Prim::Trap => (self.f)(env, args),
+            _ => (self.f)(env, &attr::strip(args)),
+        }
+    }
+    pub fn prim(&self) -> &Prim {
+        &self.prim
+    }
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+    pub fn with_span(self, span: Span) -> Self {
+        Primitive { span: Some(span), ..self }
+    }
+    pub fn is_control(&self) -> bool {
+        matches!(self.prim, Prim::Do | Prim::While | Prim::If)
     }
     pub fn display(&self) -> String {
         self.to_string()
     }
 }
 
-impl ToString for Primitive {
-    fn to_string(&self) -> String { 
-        self.text.to_string()
+impl std::fmt::Display for Primitive {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.text)
     }
 }
 
@@ -62,13 +183,16 @@ fn kr_first_wrapped(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
 
 fn kr_first(x: &Kr) -> Result<Kr, KrEvalError> {
     match x {
+        Kr::Bv(list) => Ok(Kr::B(first!(list, false))),
         Kr::Iv(list) => Ok(Kr::I(first!(list, 0i32))),
         Kr::Jv(list) => Ok(Kr::J(first!(list, 0i64))),
         Kr::Ev(list) => Ok(Kr::E(first!(list, 0f32))),
         Kr::Fv(list) => Ok(Kr::F(first!(list, 0f64))),
         Kr::Cv(list) => Ok(Kr::C(first!(list, b' '))),
+        Kr::Sv(list) => Ok(Kr::S(list.first().copied().unwrap_or(Sym::empty()))),
+        Kr::Enumv(d, list) => Ok(Kr::Enum(d.clone(), first!(list, u32::MAX))),
         Kr::NN(list) => Ok((*list.first().unwrap_or(&Kr::Null)).clone()),
-        _ => Err(KrEvalError::Type),
+        _ => Err(KrEvalError::type_of(&[x])),
     }
 }
 
@@ -82,13 +206,16 @@ fn kr_last_wrapped(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
 
 fn kr_last(x: &Kr) -> Result<Kr, KrEvalError>  {
     match x {
+        Kr::Bv(list) => Ok(Kr::B(last!(list, false))),
         Kr::Iv(list) => Ok(Kr::I(last!(list, 0i32))),
         Kr::Jv(list) => Ok(Kr::J(last!(list, 0i64))),
         Kr::Ev(list) => Ok(Kr::E(last!(list, 0f32))),
         Kr::Fv(list) => Ok(Kr::F(last!(list, 0f64))),
         Kr::Cv(list) => Ok(Kr::C(last!(list, b' '))),
+        Kr::Sv(list) => Ok(Kr::S(list.last().copied().unwrap_or(Sym::empty()))),
+        Kr::Enumv(d, list) => Ok(Kr::Enum(d.clone(), last!(list, u32::MAX))),
         Kr::NN(list) => Ok((*list.last().unwrap_or(&Kr::Null)).clone()),
-        _ => Err(KrEvalError::Type),
+        _ => Err(KrEvalError::type_of(&[x])),
     }
 }
 
@@ -98,16 +225,204 @@ fn kr_til_wrapped(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
 
 fn kr_til(x: &Kr) -> Result<Kr, KrEvalError> {
     match x {
-        Kr::J(n) => Ok(Kr::Jv((0..*n).collect())),
-        _ => Err(KrEvalError::Type),
+        Kr::J(n) => {
+            let mut v = list::alloc(u64::try_from(*n).unwrap_or(0))?;
+            v.extend(0..*n);
+            Ok(Kr::Jv(v))
+        },
+        _ => Err(KrEvalError::type_of(&[x])),
     }
 }
 
 fn kr_enlist(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
-    return (e, Ok(Kr::NN(args.to_vec())));
+    (e, Ok(Kr::NN(args.to_vec())))
 }
 
 fn kr_value(e: Env, v: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    if let Kr::Dict(_, values) = &v[0] { return (e, Ok((**values).clone())) };
     let r = e.val(&v[0]);
     (e, r)
-}
\ No newline at end of file
+}
+
+fn kr_key(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let res = match &args[0] {
+        Kr::Dict(keys, _) => Ok((**keys).clone()),
+        x => Err(KrEvalError::type_of(&[x])),
+    };
+    (e, res)
+}
+
+fn kr_reverse(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    (e, list::reverse(&args[0]))
+}
+
+fn kr_rotate(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let [n, x] = args else { unreachable!() };
+    let res = match list::as_long(n) {
+        Some(n) => list::rotate(n, x),
+        None => Err(KrEvalError::type_of(&[n, x])),
+    };
+    (e, res)
+}
+
+fn kr_raze(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    (e, list::raze(&args[0]))
+}
+
+fn kr_flip(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    (e, list::flip(&args[0]))
+}
+
+fn kr_string(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    (e, Ok(cast::string(&args[0])))
+}
+
+fn kr_type(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    (e, Ok(Kr::J(args[0].type_code() as i64)))
+}
+
+fn kr_control(e: Env, _args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    (e, Err(KrEvalError::NotAVerb))
+}
+
+// '"msg" raises an error with that message, which @[f;x;h] can catch
+fn kr_signal(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let msg = match &args[0] {
+        Kr::Cv(cv) => String::from_utf8_lossy(cv).to_string(),
+        Kr::C(c) => (*c as char).to_string(),
+        Kr::S(s) => s.to_string(),
+        x => return (e, Err(KrEvalError::type_of(&[x]))),
+    };
+    (e, Err(KrEvalError::User(msg)))
+}
+
+// trp[f;x;h] applies f to x, an error gives h[msg;trace] with the backtrace as a list of strings
+fn kr_trap(mut e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let [f, x, h] = args else { unreachable!() };
+    // The handler is given the trace, so it is built even inside @ or .
+    let tracing = e.set_tracing(true);
+    let (mut e, res) = f.apply(e, std::slice::from_ref(x));
+    e.set_tracing(tracing);
+    match (e, res) {
+        (mut e, Err(err)) if err.trappable() => {
+            e.take_site();
+            let trace = Kr::NN(e.take_trace().into_iter().map(|call| Kr::Cv(call.into_bytes())).collect());
+            h.apply(e, &[Kr::Cv(err.message().into_bytes()), trace])
+        },
+        res => res,
+    }
+}
+
+// system"cmd" runs the system command \cmd
+fn kr_system(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let cmd = match &args[0] {
+        Kr::Cv(cv) => String::from_utf8_lossy(cv).to_string(),
+        Kr::C(c) => (*c as char).to_string(),
+        x => return (e, Err(KrEvalError::type_of(&[x]))),
+    };
+    let (mut e, res) = system::command(e, &("\\".to_string() + &cmd));
+    // Errors point at the call to system rather than into the command
+    if res.is_err() { e.take_site(); };
+    (e, res.map_err(KrError::into_eval))
+}
+
+fn kr_like(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    (e, strings::like(&args[0], &args[1]))
+}
+
+fn kr_ss(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    (e, strings::ss(&args[0], &args[1]))
+}
+
+fn kr_ssr(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    (e, strings::ssr(&args[0], &args[1], &args[2]))
+}
+
+fn kr_vs(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    (e, strings::vs(&args[0], &args[1]))
+}
+
+fn kr_sv(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    (e, strings::sv(&args[0], &args[1]))
+}
+
+fn kr_upper(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    (e, strings::upper(&args[0]))
+}
+
+fn kr_lower(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    (e, strings::lower(&args[0]))
+}
+
+fn kr_trim(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    (e, strings::trim(&args[0]))
+}
+
+fn kr_ltrim(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    (e, strings::ltrim(&args[0]))
+}
+
+fn kr_rtrim(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    (e, strings::rtrim(&args[0]))
+}
+
+// The attribute of a list as a symbol, the empty symbol if it has none
+fn kr_attr(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let name = match &args[0] {
+        Kr::Attr(a, _) => Sym::from_str(a.attr().name()),
+        _ => Sym::empty(),
+    };
+    (e, Ok(Kr::S(name)))
+}
+
+// x in y, whether each item of x is an item of y
+fn kr_in(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    let [x, y] = args else { unreachable!() };
+    if y.count().is_none() { return (e, Err(KrEvalError::type_of(&[x, y]))) };
+    let x = x.plain();
+    let res = match x.count() {
+        None => Kr::B(y.find(x).is_some()),
+        Some(_) => Kr::Bv(x.items().iter().map(|k| y.find(k).is_some()).collect()),
+    };
+    (e, Ok(res))
+}
+
+fn kr_bin(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+    (e, attr::bin(&args[0], args[1].plain()))
+}
+
+// Primitives that pass their arguments straight to a function of math.rs
+macro_rules! math_fn {
+    ($name:ident, $f:path, 1) => {
+        fn $name(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+            (e, $f(&args[0]))
+        }
+    };
+    ($name:ident, $f:path, 2) => {
+        fn $name(e: Env, args: &[Kr]) -> (Env, Result<Kr, KrEvalError>) {
+            (e, $f(&args[0], &args[1]))
+        }
+    };
+}
+
+math_fn!(kr_sqrt, math::sqrt, 1);
+math_fn!(kr_exp, math::exp, 1);
+math_fn!(kr_log, math::log, 1);
+math_fn!(kr_xexp, math::xexp, 2);
+math_fn!(kr_xlog, math::xlog, 2);
+math_fn!(kr_abs, math::abs, 1);
+math_fn!(kr_neg, math::neg, 1);
+math_fn!(kr_signum, math::signum, 1);
+math_fn!(kr_floor, math::floor, 1);
+math_fn!(kr_ceiling, math::ceiling, 1);
+math_fn!(kr_reciprocal, math::reciprocal, 1);
+math_fn!(kr_mod, math::modulo, 2);
+math_fn!(kr_div, math::div, 2);
+math_fn!(kr_sin, math::sin, 1);
+math_fn!(kr_cos, math::cos, 1);
+math_fn!(kr_tan, math::tan, 1);
+math_fn!(kr_asin, math::asin, 1);
+math_fn!(kr_acos, math::acos, 1);
+math_fn!(kr_atan, math::atan, 1);
+math_fn!(kr_wsum, math::wsum, 2);
+math_fn!(kr_wavg, math::wavg, 2);
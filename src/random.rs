@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use crate::error::KrEvalError;
+use crate::index;
+use crate::kr::{Kr, NULL_J};
+use crate::list;
+use crate::sym::Sym;
+
+/*
+Random numbers, drawn from a generator held in Env so that a seed replays the same draws
+    n?m     n longs below m, with replacement, n?1.0 gives n floats below 1.0
+    n?y     n items of the list y, with replacement
+    -n?m    n distinct longs below m, -n?y gives n distinct items of y
+    0N?m    a permutation of til m, 0N?y shuffles y
+    n?`k    n symbols of k letters from a to p
+\S shows the seed and \S n restarts the generator from seed n
+*/
+
+pub const DEFAULT_SEED: i64 = -314159;
+
+// Splitmix64, small and good enough for simulations, not for secrets
+#[derive(Clone, Debug)]
+pub struct Rng {
+    seed: i64,
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: i64) -> Self {
+        Rng { seed, state: seed as u64 }
+    }
+    pub fn seed(&self) -> i64 {
+        self.seed
+    }
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+    // Uniform below n, rejecting the draws that would favour small numbers
+    fn below(&mut self, n: u64) -> u64 {
+        let zone = u64::MAX - u64::MAX % n;
+        loop {
+            let r = self.next();
+            if r < zone { return r % n };
+        }
+    }
+    // Uniform in [0,1)
+    fn float(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+    // n distinct numbers below m, a Fisher-Yates shuffle that only tracks the places it has swapped
+    fn deal(&mut self, n: u64, m: u64) -> Result<Vec<u64>, KrEvalError> {
+        let mut swapped: HashMap<u64, u64> = HashMap::new();
+        let mut picks = list::alloc(n)?;
+        picks.extend((0..n).map(|i| {
+            let j = i + self.below(m - i);
+            let picked = *swapped.get(&j).unwrap_or(&j);
+            let moved = *swapped.get(&i).unwrap_or(&i);
+            swapped.insert(j, moved);
+            picked
+        }));
+        Ok(picks)
+    }
+}
+
+// Positions drawn from m places: n with replacement, -n without, 0N all of them shuffled
+fn draw(rng: &mut Rng, n: i64, m: u64) -> Result<Vec<u64>, KrEvalError> {
+    match n {
+        NULL_J => rng.deal(m, m),
+        n if n < 0 && n.unsigned_abs() > m => Err(KrEvalError::Length),
+        n if n < 0 => rng.deal(n.unsigned_abs(), m),
+        n if n > 0 && m == 0 => Err(KrEvalError::Domain),
+        n => repeat(n, || rng.below(m)),
+    }
+}
+
+// n draws, an error if there is no room for them
+fn repeat<T>(n: i64, f: impl FnMut() -> T) -> Result<Vec<T>, KrEvalError> {
+    let mut out = list::alloc(u64::try_from(n).map_err(|_| KrEvalError::Domain)?)?;
+    out.extend(std::iter::repeat_with(f).take(n as usize));
+    Ok(out)
+}
+
+// Symbols of k random letters
+fn symbols(rng: &mut Rng, n: i64, k: usize) -> Result<Kr, KrEvalError> {
+    Ok(Kr::Sv(repeat(n, || {
+        let letters: Vec<u8> = (0..k).map(|_| b'a' + rng.below(16) as u8).collect();
+        Sym::new(&letters)
+    })?))
+}
+
+pub fn random(rng: &mut Rng, n: i64, y: &Kr) -> Result<Kr, KrEvalError> {
+    let count = |m: i64| u64::try_from(m).map_err(|_| KrEvalError::Domain);
+    match y {
+        Kr::J(m) => Ok(Kr::Jv(draw(rng, n, count(*m)?)?.into_iter().map(|i| i as i64).collect())),
+        Kr::I(m) => Ok(Kr::Iv(draw(rng, n, count(*m as i64)?)?.into_iter().map(|i| i as i32).collect())),
+        Kr::F(_) | Kr::E(_) if n < 0 => Err(KrEvalError::Domain),
+        Kr::F(f) => Ok(Kr::Fv(repeat(n, || rng.float() * f)?)),
+        Kr::E(f) => Ok(Kr::Ev(repeat(n, || (rng.float() * *f as f64) as f32)?)),
+        Kr::S(s) => match std::str::from_utf8(s.name()).ok().and_then(|k| k.parse::<usize>().ok()) {
+            Some(k @ 1..=8) => symbols(rng, n, k),
+            _ => Err(KrEvalError::Domain),
+        },
+        _ => match y.count() {
+            Some(m) => {
+                let picks = draw(rng, n, m as u64)?;
+                index::index(y, &Kr::Jv(picks.into_iter().map(|i| i as i64).collect()))
+            },
+            None => Err(KrEvalError::type_of(&[&Kr::J(n), y])),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rng;
+    use crate::eval::run;
+    use crate::kr::Kr;
+
+    // The same seed replays the same draws
+    #[test]
+    fn reseeded_draws_repeat() {
+        let draws = |seed| {
+            let mut rng = Rng::new(seed);
+            (0..5).map(|_| rng.below(100)).collect::<Vec<u64>>()
+        };
+        assert_eq!(draws(7), draws(7));
+        assert_ne!(draws(7), draws(8));
+        assert_eq!(run(&["5?100"]), run(&["5?100"]));
+    }
+
+    #[test]
+    fn deals_are_distinct() {
+        let Some(Kr::Jv(mut v)) = run(&["-10?10"]) else { panic!("-10?10 should give longs") };
+        v.sort();
+        assert_eq!(v, (0..10).collect::<Vec<i64>>());
+        assert_eq!(run(&["-11?10"]), None);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::error::{KrError, KrEvalError};
+use crate::eval::eval;
+use crate::init::Env;
+use crate::kr::Kr;
+use crate::lex;
+use crate::parse;
+use crate::system;
+use crate::text::Text;
+use crate::token::Span;
+
+/*
+Scripts, files of expressions evaluated in order as if each was typed at the prompt
+    An expression continues onto lines starting with whitespace, and onto any line while a
+    paren, bracket or brace is open, so functions can be written across several lines
+    A / at the start of a line or after whitespace comments out the rest of the line
+    Lines starting with \ are system commands, a \ alone on a line ends the script
+Loading stops at the first error, which is reported at its file and line
+Lambdas remember the line they were written on, so their errors also give the file and line
+*/
+
+pub fn load(e: Env, path: &str) -> (Env, Result<Kr, KrError>) {
+    match std::fs::read_to_string(path) {
+        Ok(text) => run(e, &text, &Text::from_str(path)),
+        Err(err) => (e, Err(KrError::Eval(KrEvalError::File(format!("{}: {}", path, err))))),
+    }
+}
+
+// Evaluate each expression of a script, giving the value of the last one
+pub fn run(mut e: Env, text: &str, path: &Text) -> (Env, Result<Kr, KrError>) {
+    let code = uncomment(text);
+    let source = Text::from_str(text);
+    let mut result = Kr::Null;
+    for (offset, input) in expressions(&code) {
+        let line = code[..offset].matches('\n').count() + 1;
+        let (new_env, res) = evaluate(e, input, Some((path, line)));
+        e = new_env;
+        match res {
+            Ok(value) => result = value,
+            Err(err) => {
+                // Errors without a place of their own point at the whole expression
+                e.mark_error(Span { start: 0, end: input.trim_end().len() });
+                e.locate_error(|site| site.in_file(&source, offset, path));
+                return (e, Err(err));
+            },
+        }
+    }
+    (e, Ok(result))
+}
+
+// One expression or system command, lex and parse errors mark where they are like eval errors do
+// Input from a script gives its path and the line the input starts on
+pub fn evaluate(mut e: Env, input: &str, file: Option<(&Text, usize)>) -> (Env, Result<Kr, KrError>) {
+    if input.starts_with('\\') { return system::command(e, input) };
+    let tokens = match lex::lex(input) {
+        Ok(tokens) => tokens,
+        Err((err, span)) => {
+            e.mark_error(span);
+            return (e, Err(KrError::Lex(err)));
+        },
+    };
+    let ast = match parse::parse(&tokens) {
+        Ok(ast) => ast,
+        Err((err, span)) => {
+            e.mark_error(span);
+            return (e, Err(KrError::Parse(err)));
+        },
+    };
+    let ast = match file {
+        Some((path, line)) => locate(&ast, input, path, line),
+        None => ast,
+    };
+    let (e, res) = eval(e, &ast);
+    (e, res.map_err(KrError::Eval))
+}
+
+// The ast with each lambda in it marked with the script line its { is on
+fn locate(ast: &Kr, input: &str, path: &Text, line: usize) -> Kr {
+    match ast {
+        Kr::NN(items) => Kr::NN(items.iter().map(|k| locate(k, input, path, line)).collect()),
+        Kr::Lambda(lambda) => {
+            let at = line + input[..lambda.offset()].matches('\n').count();
+            Kr::Lambda(lambda.in_file(path, at, |k| locate(k, input, path, line)))
+        },
+        other => other.clone(),
+    }
+}
+
+// The script with comments blanked out, so offsets into it are offsets into the file
+fn uncomment(text: &str) -> String {
+    let mut code = text.as_bytes().to_vec();
+    let mut start = 0;
+    for line in text.split_inclusive('\n') {
+        if !line.starts_with('\\') {
+            if let Some(i) = comment(line.as_bytes()) {
+                let end = start + line.trim_end_matches(['\n', '\r']).len();
+                code[start + i..end].fill(b' ');
+            }
+        }
+        start += line.len();
+    }
+    String::from_utf8(code).unwrap_or_default()
+}
+
+// Where a comment starts in a line, a / outside a string at the start or after whitespace
+fn comment(line: &[u8]) -> Option<usize> {
+    let mut quoted = false;
+    let mut i = 0;
+    while i < line.len() {
+        match line[i] {
+            b'"' => quoted = !quoted,
+            b'\\' if quoted => i += 1,
+            b'/' if !quoted && (i == 0 || line[i - 1].is_ascii_whitespace()) => return Some(i),
+            _ => {},
+        }
+        i += 1;
+    }
+    None
+}
+
+// Change in the number of open parens, brackets and braces over a line, ignoring strings
+fn nesting(line: &[u8]) -> i64 {
+    let mut quoted = false;
+    let mut depth = 0;
+    let mut i = 0;
+    while i < line.len() {
+        match line[i] {
+            b'"' => quoted = !quoted,
+            b'\\' if quoted => i += 1,
+            b'(' | b'[' | b'{' if !quoted => depth += 1,
+            b')' | b']' | b'}' if !quoted => depth -= 1,
+            _ => {},
+        }
+        i += 1;
+    }
+    depth
+}
+
+// Each expression of a script with the offset it starts at
+fn expressions(code: &str) -> Vec<(usize, &str)> {
+    let mut found = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut depth = 0;
+    let mut offset = 0;
+    for line in code.split_inclusive('\n') {
+        let here = offset;
+        offset += line.len();
+        let continues = start.is_some() && (depth > 0 || line.starts_with(char::is_whitespace));
+        if !continues {
+            if let Some(s) = start.take() { found.push((s, &code[s..here])) };
+            if line.trim_end() == "\\" { return found };
+            if line.trim().is_empty() { continue };
+            depth = 0;
+            start = Some(here);
+        }
+        depth += nesting(line.as_bytes());
+    }
+    if let Some(s) = start { found.push((s, &code[s..])) };
+    found
+}
@@ -0,0 +1,310 @@
+use crate::error::KrEvalError;
+use crate::kr::Kr;
+use crate::list;
+use crate::sym::Sym;
+
+/*
+Text functions over strings, and over symbols where it makes sense
+Each one extends over lists of strings and symbol vectors
+    like[x;p]       whether x matches the pattern p: * any characters, ? one character, [a-z] or [^a-z] a class
+    ss[x;y]         positions of y in x
+    ssr[x;y;z]      x with each y replaced by z
+    vs[d;x]         split x on the delimiter d, `vs on a symbol splits at dots, a number splits into digits of base d
+    sv[d;x]         join the strings x with d between them, or a symbol with dots, or digits of base d into a number
+    upper lower     change case of ascii letters
+    trim ltrim rtrim remove whitespace from both ends, the start or the end
+*/
+
+// Apply f to the text of each string or symbol in x
+fn each_text(x: &Kr, f: &dyn Fn(&[u8]) -> Result<Kr, KrEvalError>) -> Result<Kr, KrEvalError> {
+    if let Some(s) = x.symbols() { return each_text(&s, f) };
+    match x {
+        Kr::Cv(cv) => f(cv),
+        Kr::C(c) => f(&[*c]),
+        Kr::S(s) => f(s.name()),
+        Kr::Sv(sv) => Ok(Kr::from_items(sv.iter().map(|s| f(s.name())).collect::<Result<Vec<Kr>, KrEvalError>>()?)),
+        Kr::NN(list) => Ok(Kr::from_items(list.iter().map(|k| each_text(k, f)).collect::<Result<Vec<Kr>, KrEvalError>>()?)),
+        other => Err(KrEvalError::type_of(&[other])),
+    }
+}
+
+// Replace the text of strings, chars and symbols keeping their type
+fn map_text(x: &Kr, f: &dyn Fn(&[u8]) -> Vec<u8>) -> Result<Kr, KrEvalError> {
+    if let Some(s) = x.symbols() { return map_text(&s, f) };
+    match x {
+        Kr::Cv(cv) => Ok(Kr::Cv(f(cv))),
+        Kr::C(c) => Ok(f(&[*c]).first().map_or(Kr::Cv(Vec::new()), |c| Kr::C(*c))),
+        Kr::S(s) => Ok(Kr::S(Sym::new(&f(s.name())))),
+        Kr::Sv(sv) => Ok(Kr::Sv(sv.iter().map(|s| Sym::new(&f(s.name()))).collect())),
+        Kr::NN(list) => Ok(Kr::NN(list.iter().map(|k| map_text(k, f)).collect::<Result<Vec<Kr>, KrEvalError>>()?)),
+        other => Err(KrEvalError::type_of(&[other])),
+    }
+}
+
+// Bytes of a string or char argument such as a pattern or delimiter
+fn bytes(x: &Kr) -> Option<Vec<u8>> {
+    match x {
+        Kr::Cv(cv) => Some(cv.clone()),
+        Kr::C(c) => Some(vec![*c]),
+        _ => None,
+    }
+}
+
+pub fn like(x: &Kr, p: &Kr) -> Result<Kr, KrEvalError> {
+    let pattern = bytes(p).ok_or(KrEvalError::type_of(&[x, p]))?;
+    each_text(x, &|text| Ok(Kr::B(glob(text, &pattern))))
+}
+
+// One place in a pattern
+enum Unit<'a> {
+    Star,
+    Any,
+    Class(&'a [u8], bool),      // Set of characters and whether it is negated
+    Byte(u8),
+}
+
+impl Unit<'_> {
+    // Whether the unit matches one character, * never does
+    fn matches(&self, c: u8) -> bool {
+        match self {
+            Unit::Star => false,
+            Unit::Any => true,
+            Unit::Class(set, negate) => in_class(set, c) != *negate,
+            Unit::Byte(b) => *b == c,
+        }
+    }
+}
+
+fn units(pattern: &[u8]) -> Vec<Unit<'_>> {
+    let mut units = Vec::new();
+    let mut i = 0;
+    while i < pattern.len() {
+        let (unit, n) = match pattern[i] {
+            b'*' => (Unit::Star, 1),
+            b'?' => (Unit::Any, 1),
+            b'[' => match class(&pattern[i..]) {
+                Some((set, negate, n)) => (Unit::Class(set, negate), n),
+                None => (Unit::Byte(b'['), 1),
+            },
+            c => (Unit::Byte(c), 1),
+        };
+        units.push(unit);
+        i += n;
+    }
+    units
+}
+
+// Match from the left, on a mismatch going back to the last * and letting it take one more
+// character, earlier stars never need to take more so this is O(text × pattern)
+fn glob(text: &[u8], pattern: &[u8]) -> bool {
+    let units = units(pattern);
+    let (mut t, mut p) = (0, 0);
+    // Unit after the last * seen and where in the text it was tried from
+    let mut retry: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match units.get(p) {
+            Some(Unit::Star) => {
+                p += 1;
+                retry = Some((p, t));
+            },
+            Some(unit) if unit.matches(text[t]) => {
+                t += 1;
+                p += 1;
+            },
+            _ => match retry {
+                Some((after, from)) => {
+                    p = after;
+                    t = from + 1;
+                    retry = Some((after, t));
+                },
+                None => return false,
+            },
+        }
+    }
+    units[p..].iter().all(|u| matches!(u, Unit::Star))
+}
+
+// A character class at the start of a pattern, [abc] [a-z] or [^0-9]
+// Returns its set, whether it is negated and its length in the pattern
+fn class(pattern: &[u8]) -> Option<(&[u8], bool, usize)> {
+    let negate = pattern.get(1) == Some(&b'^');
+    let start = if negate { 2 } else { 1 };
+    let close = start + pattern.get(start..)?.iter().position(|c| *c == b']')?;
+    Some((&pattern[start..close], negate, close + 1))
+}
+
+fn in_class(set: &[u8], c: u8) -> bool {
+    let mut i = 0;
+    while i < set.len() {
+        if set.get(i+1) == Some(&b'-') && i + 2 < set.len() {
+            if set[i] <= c && c <= set[i+2] { return true };
+            i += 3;
+        } else {
+            if set[i] == c { return true };
+            i += 1;
+        }
+    }
+    false
+}
+
+// Start of each occurrence of y in x, not overlapping
+fn find_all(x: &[u8], y: &[u8]) -> Vec<usize> {
+    let mut found = Vec::new();
+    let mut i = 0;
+    while i + y.len() <= x.len() {
+        if x[i..].starts_with(y) {
+            found.push(i);
+            i += y.len();
+        } else {
+            i += 1;
+        }
+    }
+    found
+}
+
+pub fn ss(x: &Kr, y: &Kr) -> Result<Kr, KrEvalError> {
+    let y = bytes(y).ok_or(KrEvalError::type_of(&[x, y]))?;
+    if y.is_empty() { return Err(KrEvalError::Domain) };
+    each_text(x, &|text| Ok(Kr::Jv(find_all(text, &y).into_iter().map(|i| i as i64).collect())))
+}
+
+pub fn ssr(x: &Kr, y: &Kr, z: &Kr) -> Result<Kr, KrEvalError> {
+    let (Some(from), Some(to)) = (bytes(y), bytes(z)) else { return Err(KrEvalError::type_of(&[x, y, z])) };
+    if from.is_empty() { return Err(KrEvalError::Domain) };
+    map_text(x, &|text| {
+        let mut out: Vec<u8> = Vec::with_capacity(text.len());
+        let mut last = 0;
+        for i in find_all(text, &from) {
+            out.extend_from_slice(&text[last..i]);
+            out.extend_from_slice(&to);
+            last = i + from.len();
+        }
+        out.extend_from_slice(&text[last..]);
+        out
+    })
+}
+
+pub fn vs(d: &Kr, x: &Kr) -> Result<Kr, KrEvalError> {
+    match (d, x) {
+        // `vs`a.b.c gives `a`b`c
+        (Kr::S(s), Kr::S(sym)) if s.is_empty() => {
+            Ok(Kr::Sv(sym.name().split(|c| *c == b'.').map(Sym::new).collect()))
+        },
+        (_, _) if list::as_long(d).is_some() => {
+            let base = list::as_long(d).unwrap_or(0);
+            if base < 2 { return Err(KrEvalError::Domain) };
+            match x.count() {
+                None => {
+                    let n = list::as_long(x).ok_or(KrEvalError::type_of(&[d, x]))?;
+                    if n < 0 { return Err(KrEvalError::Domain) };
+                    Ok(Kr::Jv(digits(base, n)))
+                },
+                Some(_) => Ok(Kr::NN(x.items().iter().map(|k| vs(d, k)).collect::<Result<Vec<Kr>, KrEvalError>>()?)),
+            }
+        },
+        _ => {
+            let delim = bytes(d).ok_or(KrEvalError::type_of(&[d, x]))?;
+            if delim.is_empty() { return Err(KrEvalError::Domain) };
+            each_text(x, &|text| {
+                let mut pieces: Vec<Kr> = Vec::new();
+                let mut last = 0;
+                for i in find_all(text, &delim) {
+                    pieces.push(Kr::Cv(text[last..i].to_vec()));
+                    last = i + delim.len();
+                }
+                pieces.push(Kr::Cv(text[last..].to_vec()));
+                Ok(Kr::NN(pieces))
+            })
+        },
+    }
+}
+
+// Digits of n in a base, most significant first
+fn digits(base: i64, mut n: i64) -> Vec<i64> {
+    let mut out = Vec::new();
+    while n > 0 {
+        out.push(n % base);
+        n /= base;
+    }
+    out.reverse();
+    out
+}
+
+pub fn sv(d: &Kr, x: &Kr) -> Result<Kr, KrEvalError> {
+    match (d, x) {
+        (Kr::S(s), Kr::Sv(syms)) if s.is_empty() => {
+            Ok(Kr::S(Sym::new(&syms.iter().map(|s| s.name()).collect::<Vec<&[u8]>>().join(&b'.'))))
+        },
+        (_, _) if list::as_long(d).is_some() => {
+            let base = list::as_long(d).unwrap_or(0);
+            let digits = list::as_longs(x).ok_or(KrEvalError::type_of(&[d, x]))?;
+            let n = digits.iter().try_fold(0i64, |n, k| n.checked_mul(base)?.checked_add(*k));
+            Ok(Kr::J(n.ok_or(KrEvalError::Domain)?))
+        },
+        _ => {
+            let delim = bytes(d).ok_or(KrEvalError::type_of(&[d, x]))?;
+            let pieces: Vec<Vec<u8>> = x.items().iter()
+                .map(|k| bytes(k).ok_or(KrEvalError::type_of(&[d, k])))
+                .collect::<Result<Vec<Vec<u8>>, KrEvalError>>()?;
+            Ok(Kr::Cv(pieces.join(&delim[..])))
+        },
+    }
+}
+
+pub fn upper(x: &Kr) -> Result<Kr, KrEvalError> {
+    map_text(x, &|text| text.to_ascii_uppercase())
+}
+
+pub fn lower(x: &Kr) -> Result<Kr, KrEvalError> {
+    map_text(x, &|text| text.to_ascii_lowercase())
+}
+
+pub fn trim(x: &Kr) -> Result<Kr, KrEvalError> {
+    map_text(x, &|text| text.trim_ascii().to_vec())
+}
+
+pub fn ltrim(x: &Kr) -> Result<Kr, KrEvalError> {
+    map_text(x, &|text| text.trim_ascii_start().to_vec())
+}
+
+pub fn rtrim(x: &Kr) -> Result<Kr, KrEvalError> {
+    map_text(x, &|text| text.trim_ascii_end().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+    use crate::kr::Kr;
+
+    fn like(x: &str, p: &str) -> Option<Kr> {
+        run(&[&format!("like[\"{}\";\"{}\"]", x, p)])
+    }
+
+    #[test]
+    fn like_patterns() {
+        assert_eq!(like("abc", "a*"), Some(Kr::B(true)));
+        assert_eq!(like("abc", "a?c"), Some(Kr::B(true)));
+        assert_eq!(like("abc", "a?"), Some(Kr::B(false)));
+        assert_eq!(like("a*c", "a[*]c"), Some(Kr::B(true)));
+        assert_eq!(like("abc", "[^a]bc"), Some(Kr::B(false)));
+        assert_eq!(like("", "*"), Some(Kr::B(true)));
+        assert_eq!(like("aXbXc", "*X*X*c"), Some(Kr::B(true)));
+    }
+
+    // Occurrences don't overlap, and an empty search is an error
+    #[test]
+    fn ss_edges() {
+        assert_eq!(run(&["ss[\"aaaa\";\"aa\"]"]), Some(Kr::Jv(vec![0, 2])));
+        assert_eq!(run(&["ss[\"abc\";\"z\"]"]), Some(Kr::Jv(vec![])));
+        assert_eq!(run(&["ss[\"abc\";\"\"]"]), None);
+    }
+
+    #[test]
+    fn vs_edges() {
+        assert_eq!(run(&["vs[\",\";\"a,,b\"]"]), Some(Kr::NN(vec![Kr::Cv(b"a".to_vec()), Kr::Cv(vec![]), Kr::Cv(b"b".to_vec())])));
+        assert_eq!(run(&["vs[`;`a.b]"]), run(&["`a`b"]));
+        assert_eq!(run(&["vs[2;5]"]), Some(Kr::Jv(vec![1, 0, 1])));
+        assert_eq!(run(&["vs[1;5]"]), None);
+        assert_eq!(run(&["sv[2;vs[2;37]]"]), Some(Kr::J(37)));
+    }
+}
@@ -0,0 +1,103 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::text::Text;
+
+/*
+Symbols are interned in one global table, so a symbol is just its index in the table
+Comparing and hashing symbols compares integers, a symbol vector is a vector of u32 ids
+The text of a symbol is only needed to print it, to order symbols alphabetically or to look up
+the variable it names. Names are never freed, so each is leaked once and borrowed from then on
+Id 0 is always the empty symbol `
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct Sym(u32);
+
+struct Table {
+    names: Vec<&'static [u8]>,
+    ids: HashMap<&'static [u8], u32>,
+}
+
+thread_local! {
+    static SYMBOLS: RefCell<Table> = RefCell::new(Table { names: vec![b""], ids: HashMap::from([(&b""[..], 0)]) });
+}
+
+impl Sym {
+    // The symbol for some text, adding it to the table the first time it is seen
+    pub fn new(text: &[u8]) -> Self {
+        SYMBOLS.with_borrow_mut(|table| {
+            if let Some(id) = table.ids.get(text) { return Sym(*id) };
+            let id = table.names.len() as u32;
+            let name: &'static [u8] = Box::leak(text.into());
+            table.names.push(name);
+            table.ids.insert(name, id);
+            Sym(id)
+        })
+    }
+    pub fn from_text(text: &Text) -> Self {
+        Sym::new(&text.0)
+    }
+    pub fn from_str(s: &str) -> Self {
+        Sym::new(s.as_bytes())
+    }
+    // The null symbol
+    pub fn empty() -> Self {
+        Sym(0)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+    // The bytes of the symbol, borrowed without copying
+    pub fn name(&self) -> &'static [u8] {
+        SYMBOLS.with_borrow(|table| table.names[self.0 as usize])
+    }
+    // Printable form of the symbol, a copy of its name
+    pub fn text(&self) -> Text {
+        Text::from_slice(self.name())
+    }
+    // Alphabetical order, equal ids need no lookup
+    pub fn cmp_text(&self, other: &Sym) -> Ordering {
+        if self == other { return Ordering::Equal };
+        self.name().cmp(other.name())
+    }
+}
+
+impl std::fmt::Display for Sym {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(self.name()))
+    }
+}
+
+// The symbols an enumeration indexes into, named by the variable holding them
+// Each enumeration keeps a snapshot of the domain as it was when its values were enumerated,
+// so reassigning the domain variable later does not change what they stand for
+#[derive(Clone, Debug)]
+pub struct Domain {
+    name: Sym,
+    syms: Rc<Vec<Sym>>,
+}
+
+impl Domain {
+    pub fn new(name: Sym, syms: Vec<Sym>) -> Self {
+        Domain { name, syms: Rc::new(syms) }
+    }
+    pub fn name(&self) -> Sym {
+        self.name
+    }
+    // Symbol at an index, the empty symbol if it is out of range
+    pub fn sym(&self, i: u32) -> Sym {
+        self.syms.get(i as usize).copied().unwrap_or(Sym::empty())
+    }
+    // The snapshot indices from both can be read against: the same one, or the longer when the
+    // shorter is a prefix of it. Snapshots that disagree need the indices remapped by symbol
+    pub fn latest(&self, other: &Domain) -> Option<Domain> {
+        if self.name != other.name { return None };
+        if Rc::ptr_eq(&self.syms, &other.syms) { return Some(self.clone()) };
+        let (short, long) = if self.syms.len() <= other.syms.len() { (self, other) } else { (other, self) };
+        long.syms.starts_with(&short.syms).then(|| long.clone())
+    }
+}
@@ -0,0 +1,155 @@
+use std::time::Instant;
+
+use crate::error::{KrError, KrEvalError};
+use crate::heap;
+use crate::init::Env;
+use crate::kr::{self, Kr};
+use crate::random::Rng;
+use crate::script;
+use crate::sym::Sym;
+use crate::text::Text;
+
+/*
+System commands, a line starting with \
+    \d          current context
+    \d .ns      change the current context, \d . returns to the root
+    \e          whether a backtrace is printed after an error, \e 0 or \e 1 to set it
+    \S          seed of the random generator, \S n restarts it from seed n
+    \l file     load a script
+    \t expr     milliseconds taken to evaluate expr
+    \ts expr    milliseconds taken and the most bytes allocated at once while evaluating expr
+    \v          variables in the current context, \v .ns those in .ns
+    \f          functions in the current context, \f .ns those in .ns
+    \c          rows and columns of the console, \c rows cols sets them, values are cut to fit
+    \P          significant digits shown for floats, \P n sets them, 0 shows them all
+    \cd         current directory, \cd dir changes it, \pwd also shows it
+    \\          exit
+Code runs them with system, system"t 1+1" is \t 1+1
+*/
+
+pub fn command(e: Env, line: &str) -> (Env, Result<Kr, KrError>) {
+    let body = line.trim();
+    let body = body.strip_prefix('\\').unwrap_or(body);
+    let (cmd, arg) = match body.split_once(char::is_whitespace) {
+        Some((cmd, arg)) => (cmd, arg.trim()),
+        None => (body, &body[body.len()..]),
+    };
+    // Where the argument starts in the line, so errors in it point into the line
+    let offset = arg.as_ptr() as usize - line.as_ptr() as usize;
+    match (cmd, arg) {
+        ("\\", "") => (e, Err(KrError::Eval(KrEvalError::Exit))),
+        ("l", "") | ("t", "") | ("ts", "") => (e, Err(KrError::Eval(KrEvalError::Rank))),
+        ("l", path) => script::load(e, path),
+        ("t", expr) | ("ts", expr) => time(e, expr, offset, cmd == "ts"),
+        _ => {
+            let (e, res) = setting(e, cmd, arg);
+            (e, res.map_err(KrError::Eval))
+        },
+    }
+}
+
+// Evaluate expr, giving the time it took and with space the peak allocation over what was in use
+fn time(e: Env, expr: &str, offset: usize, space: bool) -> (Env, Result<Kr, KrError>) {
+    let start = heap::reset_peak();
+    let clock = Instant::now();
+    let (mut e, res) = script::evaluate(e, expr, None);
+    let ms = clock.elapsed().as_millis() as i64;
+    let bytes = heap::peak().saturating_sub(start) as i64;
+    if let Err(err) = res {
+        e.locate_error(|site| site.shift(offset));
+        return (e, Err(err));
+    }
+    match space {
+        true => (e, Ok(Kr::Jv(vec![ms, bytes]))),
+        false => (e, Ok(Kr::J(ms))),
+    }
+}
+
+// Names of the globals of a namespace, the current context if none is given, that are or are not functions
+fn names(e: &Env, ns: &str, functions: bool) -> Result<Kr, KrEvalError> {
+    let ns = match ns {
+        "" => e.context().clone(),
+        "." => Text::from_str(""),
+        ns if ns.starts_with('.') => Text::from_str(ns),
+        _ => return Err(KrEvalError::Domain),
+    };
+    Ok(Kr::Sv(e.globals(&ns).into_iter()
+        .filter(|(_, value)| value.is_function() == functions)
+        .map(|(name, _)| Sym::from_text(&name))
+        .collect()))
+}
+
+fn directory() -> Result<Kr, KrEvalError> {
+    match std::env::current_dir() {
+        Ok(dir) => Ok(Kr::Cv(dir.to_string_lossy().as_bytes().to_vec())),
+        Err(err) => Err(KrEvalError::File(err.to_string())),
+    }
+}
+
+// Commands that show or change a setting of the environment
+fn setting(mut e: Env, cmd: &str, arg: &str) -> (Env, Result<Kr, KrEvalError>) {
+    match (cmd, arg) {
+        ("d", "") => {
+            let context = match e.context().len() {
+                0 => Text::from_str("."),
+                _ => e.context().clone(),
+            };
+            (e, Ok(Kr::S(Sym::from_text(&context))))
+        },
+        ("d", ns) => {
+            let res = e.set_context(&Text::from_str(ns));
+            (e, res.map(|_| Kr::Null))
+        },
+        ("e", "") => {
+            let show = e.show_trace;
+            (e, Ok(Kr::B(show)))
+        },
+        ("e", "0") | ("e", "1") => {
+            e.show_trace = arg == "1";
+            (e, Ok(Kr::Null))
+        },
+        ("S", "") => {
+            let seed = e.rng.seed();
+            (e, Ok(Kr::J(seed)))
+        },
+        ("S", seed) => match seed.parse::<i64>() {
+            Ok(seed) => {
+                e.rng = Rng::new(seed);
+                (e, Ok(Kr::Null))
+            },
+            Err(_) => (e, Err(KrEvalError::Domain)),
+        },
+        ("v", ns) | ("f", ns) => {
+            let res = names(&e, ns, cmd == "f");
+            (e, res)
+        },
+        ("c", "") => {
+            let (rows, cols) = e.console;
+            (e, Ok(Kr::Iv(vec![rows as i32, cols as i32])))
+        },
+        ("c", size) => {
+            let size: Vec<Option<usize>> = size.split_whitespace().map(|n| n.parse().ok()).collect();
+            match size[..] {
+                [Some(rows), Some(cols)] if (10..=2000).contains(&rows) && (10..=2000).contains(&cols) => {
+                    e.console = (rows, cols);
+                    (e, Ok(Kr::Null))
+                },
+                _ => (e, Err(KrEvalError::Domain)),
+            }
+        },
+        ("P", "") => (e, Ok(Kr::J(kr::precision() as i64))),
+        ("P", digits) => match digits.parse::<usize>() {
+            Ok(digits @ 0..=17) => {
+                kr::set_precision(digits);
+                (e, Ok(Kr::Null))
+            },
+            _ => (e, Err(KrEvalError::Domain)),
+        },
+        ("cd", "") | ("pwd", "") => (e, directory()),
+        ("cd", dir) => match std::env::set_current_dir(dir) {
+            Ok(()) => (e, Ok(Kr::Null)),
+            Err(err) => (e, Err(KrEvalError::File(format!("{}: {}", dir, err)))),
+        },
+        _ => (e, Err(KrEvalError::NotDefined)),
+    }
+}
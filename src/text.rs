@@ -31,13 +31,27 @@ impl Text {
         for (i, c) in self.0.iter().enumerate().skip(skip) {
             if cond(c) { return i }
         }
-        return self.len()
+        self.len()
     }
 }
 
-impl ToString for Text {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for Text {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         // Convert the internal Vec<u8> to a UTF-8 encoded string
-        String::from_utf8_lossy(&self.0).into_owned()
+        write!(f, "{}", String::from_utf8_lossy(&self.0))
     }
-}
\ No newline at end of file
+}
+
+// A Text reads as its bytes, so maps keyed by Text can be searched with a borrowed name
+impl std::ops::Deref for Text {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl std::borrow::Borrow<[u8]> for Text {
+    fn borrow(&self) -> &[u8] {
+        &self.0
+    }
+}
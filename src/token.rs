@@ -1,4 +1,7 @@
+use crate::cast::{self, KrType};
+use crate::error::KrLexError;
 use crate::primitive::{Primitive, Prim};
+use crate::sym::Sym;
 use crate::text::Text;
 use crate::kr::Kr;
 use crate::operator::{Operator, Op};
@@ -10,8 +13,17 @@ impl NameToken {
     pub fn new(text: Text) -> Self {
         NameToken(text)
     }
-    fn parse(&self) -> Vec<Kr> {
-        vec![Kr::Prim(Primitive::new(Prim::Value)), Kr::S(self.0.clone())]
+    pub fn text(&self) -> Text {
+        self.0.clone()
+    }
+    fn parse(&self) -> Kr {
+        // Control words are left for eval to handle rather than looked up
+        match &self.0.0[..] {
+            b"do" => Kr::Prim(Primitive::new(Prim::Do)),
+            b"while" => Kr::Prim(Primitive::new(Prim::While)),
+            b"if" => Kr::Prim(Primitive::new(Prim::If)),
+            _ => Kr::NN(vec![Kr::Prim(Primitive::new(Prim::Value)), Kr::S(Sym::from_text(&self.0))]),
+        }
     }
 }
 
@@ -24,41 +36,75 @@ impl NumberToken {
         NumberToken(text)
     }
     fn parse(&self) -> Kr {
-        // input may be 123 or 123f or 123i etc..
+        // input may be 123 or 123f or 123i or 1.5 or 0N or 101b etc..
         let input = self.0.to_string();
-        let (num, letter) = if input.ends_with(|c: char| c.is_ascii_digit()) {
-            // input = 123
-            (&input[..], "j")
-        } else {
+        if let Some(bits) = input.strip_suffix('b') {
+            let bits: Vec<bool> = bits.bytes().map(|c| c == b'1').collect();
+            return if bits.len() == 1 { Kr::B(bits[0]) } else { Kr::Bv(bits) };
+        }
+        let (num, letter) = if input.ends_with(['i', 'j', 'e', 'f']) && input.len() > 1 {
             // input 123i or 123j or ..
             input.split_at(input.len() - 1)
+        } else if input.contains('.') || input.ends_with(['n', 'w']) {
+            // input = 1.5 or 0n or 0w
+            (&input[..], "f")
+        } else {
+            // input = 123 or 0N
+            (&input[..], "j")
         };
-        
-        match (num, letter) {
-            (num,"i") => Kr::I(num.parse().unwrap()),
-            (num,"j") => Kr::J(num.parse().unwrap()),
-            (num,"e") => Kr::E(num.parse().unwrap()),
-            (num,"f") => Kr::F(num.parse().unwrap()),
-            (_, _) => Kr::Null
-        }
+        let t = match letter {
+            "i" => KrType::Int,
+            "e" => KrType::Real,
+            "f" => KrType::Float,
+            _ => KrType::Long,
+        };
+        // Nulls (0N 0n) are whatever fails to parse
+        cast::parse_text(t, num.as_bytes())
     }
 }
 
 // A string surrounded by quotes: "example"
-// Text is the characters between the quotes
+// Text is the characters between the quotes as written, the bytes have escapes replaced:
+//     \"  \\  \n  \t  \r  and \NNN for a byte in octal
 #[derive(Clone, Debug)]
-pub struct QuotedToken(Text);
+pub struct QuotedToken(Text, Vec<u8>);
 
 impl QuotedToken {
-    pub fn new(text: Text) -> Self {
-        QuotedToken(text)
+    pub fn new(text: Text) -> Result<Self, KrLexError> {
+        let bytes = unescape(&text.0).ok_or(KrLexError::InvalidEscape)?;
+        Ok(QuotedToken(text, bytes))
     }
     fn parse(&self) -> Vec<u8> {
-        // TODO: there should be logic to handle "\t\netc.."
-        self.0.0.clone()
+        self.1.clone()
     }
 }
 
+fn unescape(text: &[u8]) -> Option<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::with_capacity(text.len());
+    let mut i = 0;
+    while i < text.len() {
+        if text[i] != b'\\' {
+            bytes.push(text[i]);
+            i += 1;
+            continue;
+        }
+        let (byte, n) = match text.get(i+1..)? {
+            [b'"', ..] => (b'"', 2),
+            [b'\\', ..] => (b'\\', 2),
+            [b'n', ..] => (b'\n', 2),
+            [b't', ..] => (b'\t', 2),
+            [b'r', ..] => (b'\r', 2),
+            [a @ b'0'..=b'3', b @ b'0'..=b'7', c @ b'0'..=b'7', ..] => ((a - b'0') * 64 + (b - b'0') * 8 + (c - b'0'), 4),
+            _ => return None,
+        };
+        bytes.push(byte);
+        i += n;
+    }
+    Some(bytes)
+}
+
+// A symbol after a backtick: `abc, several together are a vector: `a`b`c
+// Text is everything after the first backtick
 #[derive(Clone, Debug)]
 pub struct SymbolToken(Text);
 
@@ -66,35 +112,54 @@ impl SymbolToken {
     pub fn new(text: Text) -> Self {
         SymbolToken(text)
     }
-    fn parse(&self) -> Text {
-        self.0.clone()
+    fn parse(&self) -> Kr {
+        match self.0.0.contains(&b'`') {
+            true => Kr::Sv(self.0.0.split(|c| *c == b'`').map(Sym::new).collect()),
+            false => Kr::S(Sym::from_text(&self.0)),
+        }
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct OperatorToken {
     text: Text,
-    op: Op
+    op: Op,
+    compound: bool,     // Followed by : as in x+:y
 }
 
 
 impl OperatorToken {
-    pub fn new(text: Text) -> Self {
-        let op =match text.0[..] {
-            [b'+'] => Op::Addition,
-            [b'-'] => Op::Subtraction,
-            [b'*'] => Op::Multiplication,
-            [b'%'] => Op::Division,
-            [b':'] => Op::Assign,
-            [b','] => Op::Join,
+    pub fn new(text: Text) -> Result<Self, KrLexError> {
+        let compound = text.len() == 2 && text.get(1) == Some(&b':') && text.get(0) != Some(&b':');
+        let op = match text.0[..] {
+            [b':', b':'] => Op::AssignGlobal,
+            [b'+', ..] => Op::Addition,
+            [b'-', ..] => Op::Subtraction,
+            [b'*', ..] => Op::Multiplication,
+            [b'%', ..] => Op::Division,
+            [b':', ..] => Op::Assign,
+            [b',', ..] => Op::Join,
+            [b'$', ..] => Op::Cast,
+            [b'!', ..] => Op::Dict,
+            [b'@', ..] => Op::At,
+            [b'.', ..] => Op::Dot,
+            [b'#', ..] => Op::Take,
+            [b'_', ..] => Op::Drop,
+            [b'=', ..] => Op::Equal,
+            [b'<', ..] => Op::Less,
+            [b'>', ..] => Op::Greater,
+            [b'?', ..] => Op::Find,
             // [b"**"] => OperatorToken::Power,
-            _ => panic!("Unexpected token")
+            _ => return Err(KrLexError::UnknownCharacter),
         };
-        OperatorToken { text, op }
+        Ok(OperatorToken { text, op, compound })
     }
     pub fn parse(&self) -> Operator {
         Operator::new(self.op)
     }
+    pub fn is_compound(&self) -> bool {
+        self.compound
+    }
 }
 
 // Tokens that represent some Kr data
@@ -105,30 +170,31 @@ pub enum KrToken {
     Number(NumberToken),
     Quoted(QuotedToken),
     Symbol(SymbolToken),
+    Signal,                 // '
 }
 
 impl KrToken {
     pub fn parse(&self) -> Kr {
         match self {
-            KrToken::Name(name) => Kr::NN(name.parse()),
+            KrToken::Name(name) => name.parse(),
             KrToken::Operator(op) => Kr::Op(op.parse()),
             KrToken::Number(num) => num.parse(),
             KrToken::Quoted(s) => Kr::Cv(s.parse()),
-            KrToken::Symbol(s) => Kr::S(s.parse()),
+            KrToken::Symbol(s) => s.parse(),
+            KrToken::Signal => Kr::Prim(Primitive::new(Prim::Signal)),
         }
     }
-    fn get_text(&self) -> &Text {
+    // Source form of the token, quotes and backticks included
+    fn as_string(&self) -> String {
         match self {
-            KrToken::Name(name) => &name.0,
-            KrToken::Operator(op) => &op.text,
-            KrToken::Number(num) => &num.0,
-            KrToken::Quoted(s) => &s.0,
-            KrToken::Symbol(s) => &s.0,
+            KrToken::Name(name) => name.0.to_string(),
+            KrToken::Operator(op) => op.text.to_string(),
+            KrToken::Number(num) => num.0.to_string(),
+            KrToken::Quoted(s) => format!("\"{}\"", s.0),
+            KrToken::Symbol(s) => format!("`{}", s.0),
+            KrToken::Signal => "'".to_string(),
         }
     }
-    fn as_string(&self) -> String {
-        self.get_text().to_string()
-    }
 }
 
 
@@ -137,7 +203,8 @@ pub enum Token {
     KrToken(KrToken),
     LParen, RParen,         // ( )
     LBracket, RBracket,     // [ ]
-    // LBrace, RBrace,      // { }
+    LApply,                 // [ directly after a term: f[x;y]
+    LBrace, RBrace,         // { }
     SemiColon,
 }
 
@@ -148,8 +215,43 @@ impl Token {
             Token::LParen => { "(".to_string() },
             Token::RParen => { ")".to_string() },
             Token::LBracket => { "[".to_string() },
+            Token::LApply => { "[".to_string() },
             Token::RBracket => { "]".to_string() },
+            Token::LBrace => { "{".to_string() },
+            Token::RBrace => { "}".to_string() },
             Token::SemiColon => { ";".to_string() },
         }
     }
-}
\ No newline at end of file
+}
+// Byte offsets of a token within its input, end exclusive
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+// Tokens of an input along with where each one came from
+pub struct Lexed {
+    pub tokens: Vec<Token>,
+    pub spans: Vec<Span>,
+    pub source: Text,
+}
+
+impl Lexed {
+    pub fn get(&self, i: usize) -> Option<&Token> {
+        self.tokens.get(i)
+    }
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+    // Span of the i-th token, or the end of the input past the last one
+    pub fn span(&self, i: usize) -> Span {
+        match self.spans.get(i) {
+            Some(span) => *span,
+            None => Span { start: self.source.len(), end: self.source.len() + 1 },
+        }
+    }
+}